@@ -0,0 +1,320 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::{self, Write};
+
+use crate::disasm::{constant_result, find_code};
+use crate::instruction::{Instruction, Opcode, ParameterMode};
+
+/// How control gets from one block to another.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub enum EdgeKind {
+    /// Execution continues with the next instruction on the tape.
+    FallThrough,
+    /// A jump to an immediate target.
+    Jump,
+    /// A jump to a function, see `Call`.
+    Call,
+    /// From a call to the instruction the function returns to.
+    Return,
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub struct Edge {
+    /// Start of the block the edge leaves.
+    pub from: usize,
+    /// Start of the block the edge enters.
+    pub to: usize,
+    pub kind: EdgeKind,
+}
+
+/// A run of instructions always executed together, only entered at its first one.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Block {
+    pub start: usize,
+    pub instructions: Vec<Instruction>,
+}
+
+/// A jump to a function following the calling convention of compiled Intcode programs:
+/// the address of the instruction after the jump is stored, usually on the stack kept relative to the base,
+/// and the function returns by jumping to it through a relative parameter.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub struct Call {
+    /// Address of the jump.
+    pub address: usize,
+    pub function: usize,
+    /// Where the function returns to.
+    pub return_site: usize,
+    /// Cells the function reserves on the stack, when it starts by moving the relative base.
+    pub frame: Option<i64>,
+}
+
+/// A write to a fixed address holding part of an instruction.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub struct CodeWrite {
+    /// Address of the writing instruction.
+    pub address: usize,
+    /// The cell written.
+    pub cell: usize,
+    /// Address of the instruction the cell belongs to.
+    pub instruction: usize,
+}
+
+/// The control flow graph of a tape, along with everything the analysis could not resolve.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct ControlFlowGraph {
+    pub blocks: BTreeMap<usize, Block>,
+    pub edges: Vec<Edge>,
+    /// Jumps with a positional or relative target, which can go anywhere. Returns included.
+    pub indirect_jumps: Vec<usize>,
+    /// Jumps that always go to a relative target, the way functions return.
+    pub returns: Vec<usize>,
+    pub calls: Vec<Call>,
+    pub code_writes: Vec<CodeWrite>,
+}
+
+/// Splits the code of a tape, as found by the disassembler, into basic blocks linked by the jumps between them.
+pub fn analyze(tape: &[i64]) -> ControlFlowGraph {
+    let code = find_code(tape);
+
+    // Blocks start at the entry point, at jump targets, after jumps and after gaps in the code.
+    let mut leaders = BTreeSet::new();
+    leaders.insert(0);
+    let mut previous: Option<&Instruction> = None;
+    for instruction in code.values() {
+        let after_jump = |previous: &Instruction| previous.opcode.is_jump() || !previous.falls_through();
+        if previous.is_none_or(|previous| previous.next() != instruction.address || after_jump(previous)) {
+            leaders.insert(instruction.address);
+        }
+        leaders.extend(instruction.jump_target());
+        previous = Some(instruction);
+    }
+
+    let mut blocks: BTreeMap<usize, Block> = BTreeMap::new();
+    for instruction in code.values() {
+        if leaders.contains(&instruction.address) || blocks.is_empty() {
+            blocks.insert(instruction.address, Block { start: instruction.address, instructions: vec![] });
+        }
+        blocks.values_mut().next_back().unwrap().instructions.push(instruction.clone());
+    }
+
+    let mut graph = ControlFlowGraph {
+        blocks: BTreeMap::new(),
+        edges: vec![],
+        indirect_jumps: vec![],
+        returns: vec![],
+        calls: vec![],
+        code_writes: vec![],
+    };
+
+    for block in blocks.values() {
+        for instruction in &block.instructions {
+            if let Some(write) = code_write(&code, instruction) {
+                graph.code_writes.push(write);
+            }
+        }
+
+        let last = block.instructions.last().unwrap();
+        let mut successors = vec![];
+        if last.falls_through() {
+            successors.push((last.next(), EdgeKind::FallThrough));
+        }
+
+        if last.opcode.is_jump() {
+            match (last.jump_target(), last.operands[1].mode) {
+                (Some(function), _) if is_call(block, last) => {
+                    let frame = code.get(&function)
+                        .filter(|entry| entry.opcode == Opcode::Rel && entry.operands[0].mode == ParameterMode::Immediate)
+                        .map(|entry| entry.operands[0].value);
+                    graph.calls.push(Call { address: last.address, function, return_site: last.next(), frame });
+                    successors.push((function, EdgeKind::Call));
+                    successors.push((last.next(), EdgeKind::Return));
+                }
+                (Some(target), _) => successors.push((target, EdgeKind::Jump)),
+                (None, ParameterMode::Immediate) => {}
+                (None, mode) => {
+                    graph.indirect_jumps.push(last.address);
+                    if mode == ParameterMode::Relative && !last.falls_through() {
+                        graph.returns.push(last.address);
+                    }
+                }
+            }
+        }
+
+        graph.edges.extend(successors.into_iter()
+            .filter(|(to, _)| blocks.contains_key(to))
+            .map(|(to, kind)| Edge { from: block.start, to, kind }));
+    }
+
+    graph.blocks = blocks;
+    graph
+}
+
+/// Whether a jump that always goes to its immediate target is a call: the block stored the address
+/// following the jump beforehand, for the function to return to.
+fn is_call(block: &Block, jump: &Instruction) -> bool {
+    !jump.falls_through() && block.instructions.iter()
+        .any(|instruction| constant_result(instruction) == Some(jump.next() as i64))
+}
+
+/// The cell of code written by `instruction`, if it writes to a fixed address inside an instruction.
+fn code_write(code: &BTreeMap<usize, Instruction>, instruction: &Instruction) -> Option<CodeWrite> {
+    let dest = instruction.operands.last().filter(|_| instruction.opcode.writes())?;
+    if dest.mode != ParameterMode::Positional || dest.value < 0 {
+        return None;
+    }
+
+    let cell = dest.value as usize;
+    let (&start, target) = code.range(..=cell).next_back()?;
+    if cell >= target.next() {
+        return None;
+    }
+    Some(CodeWrite { address: instruction.address, cell, instruction: start })
+}
+
+impl ControlFlowGraph {
+    /// The graph in Graphviz DOT format. Function entries are drawn with a double border,
+    /// blocks writing to code in red, and indirect jumps as dashed edges to a shared `?` node.
+    pub fn to_dot(&self) -> String {
+        let functions: BTreeSet<usize> = self.calls.iter().map(|call| call.function).collect();
+        let writers: BTreeSet<usize> = self.code_writes.iter().map(|write| write.address).collect();
+
+        let mut dot = String::new();
+        writeln!(dot, "digraph cfg {{").unwrap();
+        writeln!(dot, "    node [shape=box, fontname=monospace];").unwrap();
+
+        for block in self.blocks.values() {
+            let mut label = String::new();
+            for instruction in &block.instructions {
+                write!(label, "{}: {}\\l", instruction.address, instruction).unwrap();
+            }
+
+            let mut attributes = format!("label=\"{}\"", label);
+            if functions.contains(&block.start) {
+                attributes.push_str(", peripheries=2");
+            }
+            if block.instructions.iter().any(|instruction| writers.contains(&instruction.address)) {
+                attributes.push_str(", color=red");
+            }
+            writeln!(dot, "    b{} [{}];", block.start, attributes).unwrap();
+        }
+
+        for edge in &self.edges {
+            let style = match edge.kind {
+                EdgeKind::FallThrough => "",
+                EdgeKind::Jump => " [label=\"jump\"]",
+                EdgeKind::Call => " [label=\"call\"]",
+                EdgeKind::Return => " [label=\"return\", style=dotted]",
+            };
+            writeln!(dot, "    b{} -> b{}{};", edge.from, edge.to, style).unwrap();
+        }
+
+        if !self.indirect_jumps.is_empty() {
+            writeln!(dot, "    indirect [label=\"?\", shape=circle];").unwrap();
+        }
+        for &jump in &self.indirect_jumps {
+            let (&block, _) = self.blocks.range(..=jump).next_back().unwrap();
+            let label = if self.returns.contains(&jump) { "ret" } else { "indirect" };
+            writeln!(dot, "    b{} -> indirect [label=\"{}\", style=dashed];", block, label).unwrap();
+        }
+
+        writeln!(dot, "}}").unwrap();
+        dot
+    }
+}
+
+impl fmt::Display for ControlFlowGraph {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Blocks: {}", self.blocks.len())?;
+        writeln!(f, "Edges: {}", self.edges.len())?;
+
+        writeln!(f, "Calls:")?;
+        for call in &self.calls {
+            write!(f, "  {:>8} calls {}, returning to {}", call.address, call.function, call.return_site)?;
+            match call.frame {
+                Some(frame) => writeln!(f, ", frame of {} cells", frame)?,
+                None => writeln!(f)?,
+            }
+        }
+
+        writeln!(f, "Indirect jumps:")?;
+        for jump in &self.indirect_jumps {
+            let kind = if self.returns.contains(jump) { " (return)" } else { "" };
+            writeln!(f, "  {:>8}{}", jump, kind)?;
+        }
+
+        writeln!(f, "Writes to code:")?;
+        for write in &self.code_writes {
+            writeln!(f, "  {:>8} writes {}, inside the instruction at {}", write.address, write.cell, write.instruction)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{analyze, Call, CodeWrite, Edge, EdgeKind};
+    use crate::assemble;
+
+    fn call_program() -> Vec<i64> {
+        // Calls a function at 10 that outputs 2 and returns, then outputs 1.
+        vec![
+            21101, 7, 0, 0, 1105, 1, 10,
+            104, 1, 99,
+            109, 1, 104, 2, 109, -1, 2106, 0, 0,
+        ]
+    }
+
+    #[test]
+    fn test_blocks_and_edges() {
+        let tape = assemble("
+            st [count]
+        loop:
+            add [count], #-1, [count]
+            jnz [count], #loop
+            halt
+        count: .data 0
+        ").unwrap();
+        let graph = analyze(&tape);
+
+        assert_eq!(graph.blocks.keys().copied().collect::<Vec<_>>(), vec![0, 2, 9]);
+        assert_eq!(graph.blocks[&2].instructions.len(), 2);
+        assert_eq!(graph.edges, vec![
+            Edge { from: 0, to: 2, kind: EdgeKind::FallThrough },
+            Edge { from: 2, to: 9, kind: EdgeKind::FallThrough },
+            Edge { from: 2, to: 2, kind: EdgeKind::Jump },
+        ]);
+        assert!(graph.indirect_jumps.is_empty());
+    }
+
+    #[test]
+    fn test_calls_and_returns() {
+        let graph = analyze(&call_program());
+
+        assert_eq!(graph.calls, vec![Call { address: 4, function: 10, return_site: 7, frame: Some(1) }]);
+        assert_eq!(graph.indirect_jumps, vec![16]);
+        assert_eq!(graph.returns, vec![16]);
+        assert!(graph.edges.contains(&Edge { from: 0, to: 10, kind: EdgeKind::Call }));
+        assert!(graph.edges.contains(&Edge { from: 0, to: 7, kind: EdgeKind::Return }));
+    }
+
+    #[test]
+    fn test_indirect_jumps_and_code_writes() {
+        // Patches the target of a jump, then jumps through a positional parameter.
+        let tape = vec![1101, 7, 0, 6, 1105, 1, 7, 6, 13, 14, 99, 0, 0, 0, 10];
+        let graph = analyze(&tape);
+
+        assert_eq!(graph.code_writes, vec![CodeWrite { address: 0, cell: 6, instruction: 4 }]);
+        assert_eq!(graph.indirect_jumps, vec![7]);
+        assert!(graph.returns.is_empty());
+    }
+
+    #[test]
+    fn test_dot_export() {
+        let dot = analyze(&call_program()).to_dot();
+
+        assert!(dot.starts_with("digraph cfg {\n"));
+        assert!(dot.contains("    b10 [label=\"10: rel #1\\l12: ld #2\\l14: rel #-1\\l16: jz #0, rb+0\\l\", peripheries=2];\n"));
+        assert!(dot.contains("    b0 -> b10 [label=\"call\"];\n"));
+        assert!(dot.contains("    b10 -> indirect [label=\"ret\", style=dashed];\n"));
+        assert!(dot.ends_with("}\n"));
+    }
+}
@@ -0,0 +1,456 @@
+use std::collections::vec_deque::VecDeque;
+use std::collections::BTreeSet;
+use std::fmt::Write;
+use std::mem;
+
+use crate::disasm::find_code;
+use crate::instruction::{Instruction, Opcode, Operand, ParameterMode};
+use crate::{IntcodeError, IntcodeMachine, MachineStatus, Snapshot};
+
+/// Function generated by `compile`. Executes compiled instructions from `machine.pc` until the machine
+/// halts or waits for input, returning `Some`. Returns `None` when it reaches an instruction it cannot execute,
+/// leaving the state as it was before that instruction, or right after a write relative to the base that
+/// landed on code, with `pc` on the next instruction. Either way the interpreter can take over from `pc`.
+pub type CompiledCode = fn(&mut CompiledMachine) -> Option<()>;
+
+/// Runs a program compiled ahead of time with `compile`, with the same input and output behaviour
+/// as an `IntcodeMachine`. Once the program writes over its own code, jumps somewhere that was not compiled
+/// or does something invalid, it is handed over to an `IntcodeMachine` for the rest of its execution.
+pub struct CompiledMachine {
+    /// Address of the next instruction, set by the generated code.
+    pub pc: usize,
+    /// Relative base, set by the generated code.
+    pub rb: i64,
+    tape: Vec<i64>,
+    /// Whether each cell holds part of a compiled instruction.
+    code: Vec<bool>,
+    code_written: bool,
+    input: VecDeque<i64>,
+    output: Vec<i64>,
+    status: MachineStatus,
+    run: CompiledCode,
+    fallback: Option<IntcodeMachine>,
+}
+
+impl CompiledMachine {
+    /// Builds a machine from the constants and function of a generated module.
+    pub fn new(tape: &[i64], code: &[usize], run: CompiledCode) -> CompiledMachine {
+        let mut cells = vec![false; tape.len()];
+        code.iter().for_each(|&address| cells[address] = true);
+
+        CompiledMachine {
+            pc: 0,
+            rb: 0,
+            tape: tape.to_vec(),
+            code: cells,
+            code_written: false,
+            input: VecDeque::new(),
+            output: vec![],
+            status: MachineStatus::Run,
+            run,
+            fallback: None,
+        }
+    }
+
+    pub fn with_input(mut self, input: i64) -> Self {
+        self.add_input(input);
+        self
+    }
+
+    pub fn add_input(&mut self, input: i64) {
+        if let Some(machine) = &mut self.fallback {
+            machine.add_input(input);
+        } else if self.status != MachineStatus::Halt {
+            if self.status == MachineStatus::Yield {
+                self.status = MachineStatus::Run;
+            }
+            self.input.push_back(input);
+        }
+    }
+
+    pub fn status(&self) -> MachineStatus {
+        self.fallback.as_ref().map_or(self.status, IntcodeMachine::status)
+    }
+
+    pub fn halted(&self) -> bool {
+        self.status() == MachineStatus::Halt
+    }
+
+    pub fn yielded(&self) -> bool {
+        self.status() == MachineStatus::Yield
+    }
+
+    /// Whether the program was handed over to the interpreter.
+    pub fn interpreted(&self) -> bool {
+        self.fallback.is_some()
+    }
+
+    pub fn tape(&self) -> &[i64] {
+        self.fallback.as_ref().map_or(&self.tape, IntcodeMachine::tape)
+    }
+
+    /// Runs until the machine halts or waits for input, returning the output produced along the way.
+    /// Panics if the program is malformed, see `try_run` for a non-panicking version.
+    pub fn run(&mut self) -> Vec<i64> {
+        self.try_run().unwrap_or_else(|error| panic!("{}", error))
+    }
+
+    pub fn try_run(&mut self) -> Result<Vec<i64>, IntcodeError> {
+        self.output.clear();
+
+        if self.fallback.is_none() {
+            self.status = MachineStatus::Run;
+            if (self.run)(self).is_some() {
+                return Ok(self.output.clone());
+            }
+
+            let snapshot = Snapshot::new(mem::take(&mut self.tape), self.pc, self.rb as isize, mem::take(&mut self.input));
+            let mut machine = IntcodeMachine::new(vec![]);
+            machine.restore(&snapshot);
+            self.fallback = Some(machine);
+        }
+
+        let machine = self.fallback.as_mut().unwrap();
+        let output = machine.try_run()?;
+        self.output.extend(output);
+        Ok(self.output.clone())
+    }
+
+    /// Grows the tape to reach `address` the same way `DenseMemory` does.
+    fn grow(&mut self, address: usize) {
+        if address >= self.tape.len() {
+            self.tape.resize((address * 2).max(address + 1), 0);
+        }
+    }
+
+    /// Checks an address the way the interpreter does, growing the tape to reach it.
+    fn address(&mut self, address: i64) -> Option<usize> {
+        if address < 0 {
+            return None;
+        }
+        self.grow(address as usize);
+        Some(address as usize)
+    }
+
+    /// Reads the cell at `address`. Used by the generated code.
+    pub fn read(&mut self, address: i64) -> Option<i64> {
+        let address = self.address(address)?;
+        Some(self.tape[address])
+    }
+
+    /// Reads the cell at `offset` from the relative base. Used by the generated code.
+    pub fn read_relative(&mut self, offset: i64) -> Option<i64> {
+        self.read(self.rb.checked_add(offset)?)
+    }
+
+    /// Resolves a write to `offset` from the relative base. Used by the generated code.
+    pub fn dest_relative(&mut self, offset: i64) -> Option<usize> {
+        self.address(self.rb.checked_add(offset)?)
+    }
+
+    /// Writes a cell, noting whether it belonged to compiled code. Used by the generated code.
+    pub fn store(&mut self, address: usize, value: i64) {
+        self.grow(address);
+        self.tape[address] = value;
+        self.code_written |= self.code.get(address).copied().unwrap_or(false);
+    }
+
+    /// Whether the compiled code can no longer be trusted. Used by the generated code.
+    pub fn code_written(&self) -> bool {
+        self.code_written
+    }
+
+    /// Takes the next input value. Used by the generated code.
+    pub fn input(&mut self) -> Option<i64> {
+        self.input.pop_front()
+    }
+
+    /// Used by the generated code.
+    pub fn emit(&mut self, value: i64) {
+        self.output.push(value);
+    }
+
+    /// Moves `pc` to `target` if it lies inside the tape. Used by the generated code.
+    pub fn jump(&mut self, target: i64) -> Option<()> {
+        if target < 0 || target as usize >= self.tape.len() {
+            return None;
+        }
+        self.pc = target as usize;
+        Some(())
+    }
+
+    /// Stops for input. Used by the generated code.
+    pub fn wait(&mut self) -> Option<()> {
+        self.status = MachineStatus::Yield;
+        Some(())
+    }
+
+    /// Used by the generated code.
+    pub fn halt(&mut self) -> Option<()> {
+        self.status = MachineStatus::Halt;
+        Some(())
+    }
+}
+
+/// Rust expression reading a parameter.
+fn read(operand: &Operand) -> String {
+    match operand.mode {
+        ParameterMode::Positional => format!("m.read({})?", operand.value),
+        ParameterMode::Immediate => operand.value.to_string(),
+        ParameterMode::Relative => format!("m.read_relative({})?", operand.value),
+    }
+}
+
+/// Rust statements for an instruction, or `None` if it has to be left to the interpreter.
+fn compile_instruction(instruction: &Instruction, code: &BTreeSet<usize>) -> Option<String> {
+    let operands = &instruction.operands;
+    let value = |index: usize| read(&operands[index]);
+    let next = instruction.next();
+
+    // Writes to a known address can be checked now, writes relative to the base only when they happen.
+    let mut check = "";
+    let dest = match operands.last() {
+        Some(&Operand { mode: ParameterMode::Positional, value }) if instruction.opcode.writes() => {
+            if value < 0 || code.contains(&(value as usize)) {
+                return None;
+            }
+            value.to_string()
+        }
+        Some(&Operand { mode: ParameterMode::Relative, value }) if instruction.opcode.writes() => {
+            check = "\n                if m.code_written() {\n                    return None;\n                }";
+            format!("m.dest_relative({})?", value)
+        }
+        // Immediate writes are invalid, the interpreter reports them.
+        Some(_) if instruction.opcode.writes() => return None,
+        _ => String::new(),
+    };
+
+    let body = match instruction.opcode {
+        Opcode::Add | Opcode::Mul | Opcode::Tlt | Opcode::Teq => {
+            let result = match instruction.opcode {
+                Opcode::Add => "a.checked_add(b)?",
+                Opcode::Mul => "a.checked_mul(b)?",
+                Opcode::Tlt => "(a < b) as i64",
+                _ => "(a == b) as i64",
+            };
+            format!(
+                "let a: i64 = {};\n                let b: i64 = {};\n                let dest = {};\n                \
+                 m.store(dest, {});\n                m.pc = {};",
+                value(0), value(1), dest, result, next
+            )
+        }
+        Opcode::St => format!(
+            "let dest = {};\n                let input = match m.input() {{\n                    \
+             Some(input) => input,\n                    None => return m.wait(),\n                }};\n                \
+             m.store(dest, input);\n                m.pc = {};",
+            dest, next
+        ),
+        Opcode::Ld => format!("let a: i64 = {};\n                m.emit(a);\n                m.pc = {};", value(0), next),
+        Opcode::Jnz | Opcode::Jz => format!(
+            "let a: i64 = {};\n                let b: i64 = {};\n                if a {} 0 {{\n                    m.jump(b)?;\n                \
+             }} else {{\n                    m.pc = {};\n                }}",
+            value(0), value(1), if instruction.opcode == Opcode::Jnz { "!=" } else { "==" }, next
+        ),
+        Opcode::Rel => format!("let a: i64 = {};\n                m.rb = m.rb.checked_add(a)?;\n                m.pc = {};", value(0), next),
+        Opcode::Halt => "return m.halt();".to_string(),
+        // The disassembler only finds built-in opcodes.
+        Opcode::Custom { .. } => return None,
+    };
+    Some(body + check)
+}
+
+/// Translates a program into the source of a Rust module with the same behaviour, to be run with `CompiledMachine`.
+/// Every instruction found by the disassembler becomes an arm of a `match` on the program counter.
+/// Instructions that write to a cell holding code at a known address are left to the interpreter,
+/// and so is everything following a write that turns out to hit code at run time.
+/// Reads need no such care, as the compiled program keeps the tape up to date.
+pub fn compile(tape: &[i64]) -> String {
+    let instructions = find_code(tape);
+    let code: BTreeSet<usize> = instructions.values().flat_map(|instruction| instruction.address..instruction.next()).collect();
+
+    let mut source = String::new();
+    writeln!(source, "// Generated by intcode-compile from a {} cell tape. Do not edit.", tape.len()).unwrap();
+    writeln!(source, "use intcode::CompiledMachine;").unwrap();
+    writeln!(source).unwrap();
+
+    let cells: Vec<String> = tape.iter().map(i64::to_string).collect();
+    writeln!(source, "pub const TAPE: &[i64] = &[{}];", cells.join(", ")).unwrap();
+    writeln!(source).unwrap();
+    writeln!(source, "/// Cells holding compiled instructions.").unwrap();
+    let cells: Vec<String> = code.iter().map(usize::to_string).collect();
+    writeln!(source, "pub const CODE: &[usize] = &[{}];", cells.join(", ")).unwrap();
+    writeln!(source).unwrap();
+
+    writeln!(source, "pub fn machine() -> CompiledMachine {{").unwrap();
+    writeln!(source, "    CompiledMachine::new(TAPE, CODE, run)").unwrap();
+    writeln!(source, "}}").unwrap();
+    writeln!(source).unwrap();
+
+    writeln!(source, "pub fn run(m: &mut CompiledMachine) -> Option<()> {{").unwrap();
+    writeln!(source, "    loop {{").unwrap();
+    writeln!(source, "        match m.pc {{").unwrap();
+    for instruction in instructions.values() {
+        if let Some(body) = compile_instruction(instruction, &code) {
+            writeln!(source, "            {} => {{", instruction.address).unwrap();
+            writeln!(source, "                // {}", instruction).unwrap();
+            writeln!(source, "                {}", body).unwrap();
+            writeln!(source, "            }}").unwrap();
+        }
+    }
+    writeln!(source, "            _ => return None,").unwrap();
+    writeln!(source, "        }}").unwrap();
+    writeln!(source, "    }}").unwrap();
+    writeln!(source, "}}").unwrap();
+    source
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{compile, CompiledMachine};
+    use crate::{parse_tape, IntcodeMachine};
+
+    // What `compile` generates for a few sample programs, built along with the tests.
+    // `test_generated_code_is_current` fails when they need to be generated again with `intcode-compile`.
+    mod quine {
+        include!("testdata/quine.rs");
+    }
+    mod compare {
+        include!("testdata/compare.rs");
+    }
+    mod large {
+        include!("testdata/large.rs");
+    }
+
+    const GENERATED: [(&str, &str); 3] = [
+        (include_str!("testdata/quine.rs"), "109,1,204,-1,1001,100,1,100,1008,100,16,101,1006,101,0,99"),
+        (
+            include_str!("testdata/compare.rs"),
+            "3,21,1008,21,8,20,1005,20,22,107,8,21,20,1006,20,31,1106,0,36,98,0,0,1002,21,125,20,4,20,\
+             1105,1,46,104,999,1105,1,46,1101,1000,1,20,4,20,1105,1,46,98,99",
+        ),
+        (
+            include_str!("testdata/large.rs"),
+            "1108,5000000000,5000000000,22,1005,22,9,104,0,104,1125899906842624,\
+             1102,34915192,34915192,23,4,23,1105,5000000000,21,99,99,0,0",
+        ),
+    ];
+
+    /// What `compile` generates for `vec![3, 9, 1002, 9, 2, 9, 4, 9, 99, 0]`.
+    fn doubler(m: &mut CompiledMachine) -> Option<()> {
+        loop {
+            match m.pc {
+                0 => {
+                    // st [9]
+                    let dest = 9;
+                    let input = match m.input() {
+                        Some(input) => input,
+                        None => return m.wait(),
+                    };
+                    m.store(dest, input);
+                    m.pc = 2;
+                }
+                2 => {
+                    // mul [9], #2, [9]
+                    let a: i64 = m.read(9)?;
+                    let b: i64 = 2;
+                    let dest = 9;
+                    m.store(dest, a.checked_mul(b)?);
+                    m.pc = 6;
+                }
+                6 => {
+                    // ld [9]
+                    let a: i64 = m.read(9)?;
+                    m.emit(a);
+                    m.pc = 8;
+                }
+                8 => {
+                    // halt
+                    return m.halt();
+                }
+                _ => return None,
+            }
+        }
+    }
+
+    #[test]
+    fn test_compile() {
+        let source = compile(&[3, 9, 1002, 9, 2, 9, 4, 9, 99, 0]);
+
+        assert!(source.contains("pub const CODE: &[usize] = &[0, 1, 2, 3, 4, 5, 6, 7, 8];\n"));
+        assert!(source.contains("\
+            2 => {
+                // mul [9], #2, [9]
+                let a: i64 = m.read(9)?;
+                let b: i64 = 2;
+                let dest = 9;
+                m.store(dest, a.checked_mul(b)?);
+                m.pc = 6;
+            }
+"));
+    }
+
+    #[test]
+    fn test_generated_code_is_current() {
+        for (source, tape) in GENERATED.iter() {
+            assert_eq!(&compile(&parse_tape(tape).unwrap()), source);
+        }
+    }
+
+    #[test]
+    fn test_generated_code_runs() {
+        let mut machine = quine::machine();
+        assert_eq!(machine.run(), quine::TAPE);
+        assert!(!machine.interpreted());
+
+        for input in 6..11 {
+            let mut machine = compare::machine().with_input(input);
+            assert_eq!(machine.run(), IntcodeMachine::new(compare::TAPE.to_vec()).with_input(input).run());
+            assert!(!machine.interpreted());
+        }
+
+        let mut machine = large::machine();
+        assert_eq!(machine.run(), vec![1125899906842624, 1219070632396864]);
+        assert!(machine.halted());
+        assert!(!machine.interpreted());
+    }
+
+    #[test]
+    fn test_compile_leaves_writes_to_code_to_interpreter() {
+        // Overwrites the operand of the `ld` that follows.
+        let source = compile(&[1101, 1, 1, 5, 104, 0, 99]);
+
+        assert!(!source.contains("            0 => {"));
+        assert!(source.contains("            4 => {"));
+        assert!(source.contains("            6 => {"));
+    }
+
+    #[test]
+    fn test_compiled_machine() {
+        let tape = [3, 9, 1002, 9, 2, 9, 4, 9, 99, 0];
+        let mut machine = CompiledMachine::new(&tape, &[0, 1, 2, 3, 4, 5, 6, 7, 8], doubler);
+
+        assert_eq!(machine.run(), vec![]);
+        assert!(machine.yielded());
+        machine.add_input(21);
+        assert_eq!(machine.run(), vec![42]);
+        assert!(machine.halted());
+        assert!(!machine.interpreted());
+    }
+
+    #[test]
+    fn test_falls_back_to_interpreter() {
+        // Jumps to an instruction that was not compiled.
+        let tape = [1105, 1, 3, 104, 5, 99];
+        let mut machine = CompiledMachine::new(&tape, &[0, 1, 2], |m| match m.pc {
+            0 => {
+                m.jump(3)?;
+                None
+            }
+            _ => None,
+        });
+
+        assert_eq!(machine.run(), vec![5]);
+        assert!(machine.interpreted());
+        assert!(machine.halted());
+    }
+}
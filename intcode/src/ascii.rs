@@ -0,0 +1,159 @@
+use std::collections::vec_deque::VecDeque;
+
+use crate::{IntcodeError, IntcodeMachine};
+
+const NEWLINE: i64 = b'\n' as i64;
+
+/// Something printed by a program speaking ASCII.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum AsciiEvent {
+    /// A line of text, without its newline.
+    Line(String),
+    /// A value outside the ASCII range, usually the answer the program was computing.
+    Value(i64),
+}
+
+fn is_ascii(value: i64) -> bool {
+    (0..128).contains(&value)
+}
+
+/// Talks to a program that reads and writes ASCII codes, one character per value, a line at a time.
+pub struct AsciiMachine {
+    machine: IntcodeMachine,
+    /// Output produced by the machine and not yet read.
+    output: VecDeque<i64>,
+}
+
+impl AsciiMachine {
+    pub fn new(machine: IntcodeMachine) -> AsciiMachine {
+        AsciiMachine { machine, output: VecDeque::new() }
+    }
+
+    pub fn machine(&self) -> &IntcodeMachine {
+        &self.machine
+    }
+
+    pub fn machine_mut(&mut self) -> &mut IntcodeMachine {
+        &mut self.machine
+    }
+
+    pub fn into_inner(self) -> IntcodeMachine {
+        self.machine
+    }
+
+    /// Queues the characters of `line` followed by a newline as input.
+    /// Characters outside the ASCII range are sent as their Unicode code point.
+    pub fn send_line(&mut self, line: &str) {
+        line.chars().for_each(|c| self.machine.add_input(c as i64));
+        self.machine.add_input(NEWLINE);
+    }
+
+    /// Returns the next line or non-ASCII value printed by the program, running it as needed.
+    /// When the program stops for input or halts in the middle of a line, the text printed so far is returned,
+    /// which is usually a prompt. Returns `None` once the program stopped with nothing left to read.
+    pub fn read_line(&mut self) -> Result<Option<AsciiEvent>, IntcodeError> {
+        loop {
+            if let Some(event) = self.next_event(false) {
+                return Ok(Some(event));
+            }
+            if !self.resume()? {
+                return Ok(self.next_event(true));
+            }
+        }
+    }
+
+    /// Runs the program until it waits for input or halts, returning everything it printed.
+    pub fn read_until_prompt(&mut self) -> Result<Vec<AsciiEvent>, IntcodeError> {
+        while self.resume()? {}
+
+        let mut events = vec![];
+        while let Some(event) = self.next_event(true) {
+            events.push(event);
+        }
+        Ok(events)
+    }
+
+    /// Runs the machine if it can make progress, returning whether it did.
+    fn resume(&mut self) -> Result<bool, IntcodeError> {
+        let machine = &self.machine;
+        if machine.stopped() || (machine.yielded() && machine.pending_input().next().is_none()) {
+            return Ok(false);
+        }
+
+        let output = self.machine.try_run()?;
+        self.output.extend(output);
+        Ok(true)
+    }
+
+    /// Takes the next event out of the output read so far.
+    /// Unless `partial` is set, text not yet terminated by a newline or a non-ASCII value is left alone.
+    fn next_event(&mut self, partial: bool) -> Option<AsciiEvent> {
+        let first = *self.output.front()?;
+        if !is_ascii(first) {
+            self.output.pop_front();
+            return Some(AsciiEvent::Value(first));
+        }
+
+        let end = match self.output.iter().position(|&value| value == NEWLINE || !is_ascii(value)) {
+            Some(end) => end,
+            None if partial => self.output.len(),
+            None => return None,
+        };
+        let line = self.output.drain(..end).map(|value| value as u8 as char).collect();
+        if self.output.front() == Some(&NEWLINE) {
+            self.output.pop_front();
+        }
+        Some(AsciiEvent::Line(line))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AsciiEvent, AsciiMachine};
+    use crate::{assemble, IntcodeMachine};
+
+    fn greeter() -> AsciiMachine {
+        // Prints a greeting, a score and a prompt, then echoes a line back.
+        let tape = assemble("
+            ld #72
+            ld #105
+            ld #10
+            ld #1000
+            ld #62
+        echo:
+            st [char]
+            ld [char]
+            teq [char], #10, [test]
+            jz [test], #echo
+            halt
+        char: .data 0
+        test: .data 0
+        ").unwrap();
+        AsciiMachine::new(IntcodeMachine::new(tape))
+    }
+
+    fn line(text: &str) -> AsciiEvent {
+        AsciiEvent::Line(text.to_string())
+    }
+
+    #[test]
+    fn test_read_line() {
+        let mut machine = greeter();
+
+        assert_eq!(machine.read_line().unwrap(), Some(line("Hi")));
+        assert_eq!(machine.read_line().unwrap(), Some(AsciiEvent::Value(1000)));
+        assert_eq!(machine.read_line().unwrap(), Some(line(">")));
+        assert_eq!(machine.read_line().unwrap(), None);
+        assert!(machine.machine().yielded());
+    }
+
+    #[test]
+    fn test_conversation() {
+        let mut machine = greeter();
+        assert_eq!(machine.read_until_prompt().unwrap(), vec![line("Hi"), AsciiEvent::Value(1000), line(">")]);
+
+        machine.send_line("hello");
+        assert_eq!(machine.read_until_prompt().unwrap(), vec![line("hello")]);
+        assert!(machine.machine().halted());
+    }
+}
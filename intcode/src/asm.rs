@@ -0,0 +1,346 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+
+use crate::instruction::{Opcode, ParameterMode};
+
+/// An error found while assembling, pointing at the offending line and column (both starting at 1).
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct AssembleError {
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl fmt::Display for AssembleError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.line, self.column, self.message)
+    }
+}
+
+impl Error for AssembleError {}
+
+/// A number, or a label that is replaced by its address once every label is known.
+#[derive(Debug, Clone)]
+enum Value {
+    Number(i64),
+    Label { name: String, line: usize, column: usize },
+}
+
+/// A source line turned into the tape cells it produces.
+#[derive(Debug, Clone)]
+enum Statement {
+    Instruction { opcode: Opcode, operands: Vec<(ParameterMode, Value)> },
+    Data(Vec<Value>),
+}
+
+/// Assembles source text into a tape that can be loaded with `IntcodeMachine::new`.
+///
+/// Every line holds an optional `label:` definition followed by an instruction, a `.data` directive or nothing.
+/// Instructions use the same mnemonics as the machine (`add`, `mul`, `st`, `ld`, `jnz`, `jz`, `tlt`, `teq`,
+/// `rel`, `halt`) with comma separated operands: `[addr]` for positional, `#value` for immediate and `rb+off`
+/// for relative parameters. `.data` takes a comma separated list of values. Addresses and values may be
+/// numbers or labels, and everything after a `;` is a comment.
+/// This is the syntax printed by `disassemble`, so a disassembly assembles back into the original tape.
+pub fn assemble(source: &str) -> Result<Vec<i64>, AssembleError> {
+    let mut labels: HashMap<String, usize> = HashMap::new();
+    let mut statements = vec![];
+    let mut address = 0;
+
+    for (index, text) in source.lines().enumerate() {
+        let mut parser = LineParser { text, line: index + 1, position: 0 };
+
+        while let Some((name, column)) = parser.label_definition() {
+            if labels.insert(name.to_string(), address).is_some() {
+                return Err(parser.error(column, format!("Label {} is defined more than once", name)));
+            }
+        }
+
+        if let Some(statement) = parser.statement()? {
+            address += match &statement {
+                Statement::Instruction { operands, .. } => operands.len() + 1,
+                Statement::Data(values) => values.len(),
+            };
+            statements.push(statement);
+        }
+    }
+
+    let resolve = |value: &Value| -> Result<i64, AssembleError> {
+        match value {
+            Value::Number(number) => Ok(*number),
+            Value::Label { name, line, column } => labels.get(name)
+                .map(|&address| address as i64)
+                .ok_or_else(|| AssembleError {
+                    line: *line,
+                    column: *column,
+                    message: format!("Undefined label {}", name),
+                }),
+        }
+    };
+
+    let mut tape = Vec::with_capacity(address);
+    for statement in &statements {
+        match statement {
+            Statement::Instruction { opcode, operands } => {
+                let modes = operands.iter().rev().fold(0, |modes, (mode, _)| modes * 10 + mode.digit());
+                tape.push(modes * 100 + opcode.code());
+                for (_, value) in operands {
+                    tape.push(resolve(value)?);
+                }
+            }
+            Statement::Data(values) => {
+                for value in values {
+                    tape.push(resolve(value)?);
+                }
+            }
+        }
+    }
+
+    Ok(tape)
+}
+
+/// Walks a single source line, keeping track of the current column for error reporting.
+struct LineParser<'a> {
+    text: &'a str,
+    line: usize,
+    position: usize,
+}
+
+impl<'a> LineParser<'a> {
+    fn error(&self, column: usize, message: String) -> AssembleError {
+        AssembleError { line: self.line, column, message }
+    }
+
+    /// The part of the line that has not been parsed yet, without the comment.
+    fn rest(&self) -> &'a str {
+        let text = &self.text[self.position..];
+        match text.find(';') {
+            Some(comment) => &text[..comment],
+            None => text,
+        }
+    }
+
+    fn skip_whitespace(&mut self) {
+        let rest = self.rest();
+        self.position += rest.len() - rest.trim_start().len();
+    }
+
+    fn column(&self) -> usize {
+        self.position + 1
+    }
+
+    /// Consumes a `name:` label definition, returning the label and the column it starts at.
+    fn label_definition(&mut self) -> Option<(&'a str, usize)> {
+        self.skip_whitespace();
+        let rest = self.rest();
+        let length = identifier_length(rest);
+
+        if length > 0 && rest[length..].starts_with(':') {
+            let column = self.column();
+            self.position += length + 1;
+            Some((&rest[..length], column))
+        } else {
+            None
+        }
+    }
+
+    fn statement(&mut self) -> Result<Option<Statement>, AssembleError> {
+        self.skip_whitespace();
+        let rest = self.rest();
+        if rest.is_empty() {
+            return Ok(None);
+        }
+
+        let column = self.column();
+        let directive = rest.starts_with('.');
+        let length = identifier_length(&rest[directive as usize..]) + directive as usize;
+        let name = &rest[..length];
+        if name.is_empty() || name == "." {
+            return Err(self.error(column, format!("Expected an instruction or directive, found {}", rest.trim_end())));
+        }
+        self.position += length;
+
+        let arguments = self.arguments()?;
+
+        if directive {
+            if name != ".data" {
+                return Err(self.error(column, format!("Unknown directive {}", name)));
+            }
+            if arguments.is_empty() {
+                return Err(self.error(column, ".data needs at least one value".to_string()));
+            }
+
+            let values = arguments.iter()
+                .map(|&(argument, column)| self.value(argument, column))
+                .collect::<Result<Vec<Value>, AssembleError>>()?;
+            return Ok(Some(Statement::Data(values)));
+        }
+
+        let opcode = Opcode::from_mnemonic(name)
+            .ok_or_else(|| self.error(column, format!("Unknown instruction {}", name)))?;
+
+        if arguments.len() != opcode.arity() {
+            return Err(self.error(column, format!(
+                "{} takes {} operands but {} were given", name, opcode.arity(), arguments.len()
+            )));
+        }
+
+        let mut operands = vec![];
+        for (index, &(argument, column)) in arguments.iter().enumerate() {
+            let operand = self.operand(argument, column)?;
+
+            if opcode.writes() && index == arguments.len() - 1 && operand.0 == ParameterMode::Immediate {
+                return Err(self.error(column, format!("{} cannot write to an immediate operand", name)));
+            }
+            operands.push(operand);
+        }
+
+        Ok(Some(Statement::Instruction { opcode, operands }))
+    }
+
+    /// Splits the rest of the line on commas, returning every trimmed argument with its column.
+    fn arguments(&mut self) -> Result<Vec<(&'a str, usize)>, AssembleError> {
+        let rest = self.rest();
+        if !rest.is_empty() && !rest.starts_with(char::is_whitespace) {
+            return Err(self.error(self.column(), format!("Unexpected {}", rest.trim_end())));
+        }
+        if rest.trim().is_empty() {
+            return Ok(vec![]);
+        }
+
+        let mut arguments = vec![];
+        let mut start = self.position;
+        for piece in rest.split(',') {
+            let trimmed = piece.trim();
+            let column = start + (piece.len() - piece.trim_start().len()) + 1;
+
+            if trimmed.is_empty() {
+                return Err(self.error(column, "Missing operand".to_string()));
+            }
+            arguments.push((trimmed, column));
+            start += piece.len() + 1;
+        }
+
+        self.position += rest.len();
+        Ok(arguments)
+    }
+
+    fn operand(&self, text: &str, column: usize) -> Result<(ParameterMode, Value), AssembleError> {
+        if let Some(value) = text.strip_prefix('#') {
+            return Ok((ParameterMode::Immediate, self.value(value, column + 1)?));
+        }
+
+        if text.starts_with('[') {
+            if !text.ends_with(']') {
+                return Err(self.error(column, format!("Missing ] in {}", text)));
+            }
+            return Ok((ParameterMode::Positional, self.value(&text[1..text.len() - 1], column + 1)?));
+        }
+
+        if let Some(offset) = text.strip_prefix("rb") {
+            if offset.is_empty() {
+                return Ok((ParameterMode::Relative, Value::Number(0)));
+            }
+            if offset.starts_with('+') || offset.starts_with('-') {
+                return match offset.parse::<i64>() {
+                    Ok(offset) => Ok((ParameterMode::Relative, Value::Number(offset))),
+                    Err(_) => Err(self.error(column + 2, format!("Invalid relative offset {}", offset))),
+                };
+            }
+        }
+
+        Err(self.error(column, format!("Invalid operand {}, expected [addr], #value or rb+offset", text)))
+    }
+
+    fn value(&self, text: &str, column: usize) -> Result<Value, AssembleError> {
+        let text = text.trim();
+
+        if let Ok(number) = text.parse::<i64>() {
+            return Ok(Value::Number(number));
+        }
+
+        if !text.is_empty() && identifier_length(text) == text.len() {
+            return Ok(Value::Label { name: text.to_string(), line: self.line, column });
+        }
+
+        Err(self.error(column, format!("Invalid value {}", text)))
+    }
+}
+
+/// Length of the identifier at the start of `text`: a letter or underscore followed by letters, digits or underscores.
+fn identifier_length(text: &str) -> usize {
+    let mut chars = text.char_indices();
+    match chars.next() {
+        Some((_, first)) if first.is_ascii_alphabetic() || first == '_' => {}
+        _ => return 0,
+    }
+
+    chars.find(|(_, c)| !c.is_ascii_alphanumeric() && *c != '_')
+        .map(|(index, _)| index)
+        .unwrap_or_else(|| text.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{assemble, AssembleError};
+    use crate::disassemble;
+
+    #[test]
+    fn test_assemble_instructions() {
+        let source = "
+            st [9]
+            teq [9], [10], [9]
+            ld [9]
+            halt
+            .data -1, 8
+        ";
+
+        assert_eq!(assemble(source), Ok(vec![3, 9, 8, 9, 10, 9, 4, 9, 99, -1, 8]));
+    }
+
+    #[test]
+    fn test_assemble_modes() {
+        let source = "rel #2000\nadd rb+3, #-1, rb-2 ; comment\nhalt";
+
+        assert_eq!(assemble(source), Ok(vec![109, 2000, 21201, 3, -1, -2, 99]));
+    }
+
+    #[test]
+    fn test_assemble_labels() {
+        let source = "
+            st [input]
+            jz [input], #zero
+            ld #1
+            halt
+        zero: ld #0
+            halt
+        input: .data -1
+        ";
+
+        assert_eq!(assemble(source), Ok(vec![3, 11, 1006, 11, 8, 104, 1, 99, 104, 0, 99, -1]));
+    }
+
+    #[test]
+    fn test_round_trip_with_disassembler() {
+        let tape: Vec<i64> = vec![3, 21, 1008, 21, 8, 20, 1005, 20, 22, 107, 8, 21, 20, 1006, 20, 31,
+                                  1106, 0, 36, 98, 0, 0, 1002, 21, 125, 20, 4, 20, 1105, 1, 46, 104,
+                                  999, 1105, 1, 46, 1101, 1000, 1, 20, 4, 20, 1105, 1, 46, 98, 99];
+
+        assert_eq!(assemble(&disassemble(&tape).to_string()), Ok(tape));
+    }
+
+    #[test]
+    fn test_errors_have_line_and_column() {
+        let error = |line, column, message: &str| Err(AssembleError { line, column, message: message.to_string() });
+
+        assert_eq!(assemble("halt\n  jmp #1"), error(2, 3, "Unknown instruction jmp"));
+        assert_eq!(assemble("add [1], [2]"), error(1, 1, "add takes 3 operands but 2 were given"));
+        assert_eq!(assemble("add [1], [2], #3"), error(1, 15, "add cannot write to an immediate operand"));
+        assert_eq!(assemble("ld [1], "), error(1, 9, "Missing operand"));
+        assert_eq!(assemble("jz #0, #nowhere"), error(1, 9, "Undefined label nowhere"));
+        assert_eq!(assemble("a: halt\na: halt"), error(2, 1, "Label a is defined more than once"));
+        assert_eq!(assemble(".word 1"), error(1, 1, "Unknown directive .word"));
+        assert_eq!(assemble("  12, 13"), error(1, 3, "Expected an instruction or directive, found 12, 13"));
+        assert_eq!(assemble("ld rb*2"), error(1, 4, "Invalid operand rb*2, expected [addr], #value or rb+offset"));
+    }
+}
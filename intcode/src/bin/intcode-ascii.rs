@@ -0,0 +1,155 @@
+use std::env;
+use std::fmt::Display;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufWriter, Write};
+use std::process;
+
+use intcode::{parse_tape, AsciiEvent, AsciiMachine, IntcodeMachine};
+
+const USAGE: &str = "\
+Usage: intcode-ascii <tape> [options]
+Runs an ASCII Intcode program, reading its input from the terminal a line at a time.
+Lines are edited with the terminal's own line editing before they are sent.
+Options:
+  --script <file>      send the lines of file first, then continue from the terminal
+  --transcript <file>  log the whole session to file, input lines prefixed with '> '
+  --record <file>      save the input lines sent, to be replayed later with --script";
+
+fn fail<E: Display>(message: &str, error: E) -> ! {
+    eprintln!("{}: {}", message, error);
+    process::exit(1);
+}
+
+fn create(path: &str) -> BufWriter<File> {
+    BufWriter::new(File::create(path).unwrap_or_else(|error| fail(&format!("Failed to create {}", path), error)))
+}
+
+/// Formats an error for the user, prefixed by what was being done.
+fn describe<E: Display>(message: &str) -> impl Fn(E) -> String + '_ {
+    move |error| format!("{}: {}", message, error)
+}
+
+struct Session {
+    machine: AsciiMachine,
+    script: Vec<String>,
+    transcript: Option<BufWriter<File>>,
+    record: Option<BufWriter<File>>,
+}
+
+impl Session {
+    fn log(&mut self, line: &str) -> Result<(), String> {
+        if let Some(transcript) = &mut self.transcript {
+            writeln!(transcript, "{}", line).map_err(describe("Failed to write transcript"))?;
+        }
+        Ok(())
+    }
+
+    fn show_output(&mut self) -> Result<(), String> {
+        let events = self.machine.read_until_prompt().map_err(describe("Program failed"))?;
+        for event in events {
+            let line = match event {
+                AsciiEvent::Line(line) => line,
+                AsciiEvent::Value(value) => format!("[{}]", value),
+            };
+            println!("{}", line);
+            self.log(&line)?;
+        }
+        Ok(())
+    }
+
+    /// Next line to send to the program, from the script while it lasts and then from stdin.
+    fn next_input(&mut self, lines: &mut impl Iterator<Item = io::Result<String>>) -> Result<Option<String>, String> {
+        if !self.script.is_empty() {
+            let line = self.script.remove(0);
+            println!("> {}", line);
+            return Ok(Some(line));
+        }
+
+        print!("> ");
+        io::stdout().flush().map_err(describe("Failed to write to stdout"))?;
+        lines.next().transpose().map_err(describe("Failed to read stdin"))
+    }
+
+    fn run(&mut self) -> Result<(), String> {
+        let stdin = io::stdin();
+        let mut lines = stdin.lock().lines();
+
+        loop {
+            self.show_output()?;
+            if self.machine.machine().stopped() {
+                return Ok(());
+            }
+
+            let line = match self.next_input(&mut lines)? {
+                Some(line) => line,
+                None => return Ok(()),
+            };
+            self.log(&format!("> {}", line))?;
+            if let Some(record) = &mut self.record {
+                writeln!(record, "{}", line).map_err(describe("Failed to write record"))?;
+            }
+            self.machine.send_line(&line);
+        }
+    }
+
+    /// Writes out the logs, whether or not the session ended with an error, which goes in the transcript too.
+    fn finish(&mut self, result: Result<(), String>) -> Result<(), String> {
+        if let Err(error) = &result {
+            self.log(error).ok();
+        }
+        for writer in self.transcript.iter_mut().chain(self.record.iter_mut()) {
+            writer.flush().map_err(describe("Failed to write log"))?;
+        }
+        result
+    }
+}
+
+fn main() {
+    let arguments: Vec<String> = env::args().skip(1).collect();
+    let path = match arguments.first() {
+        Some(path) if !path.starts_with("--") => path,
+        _ => {
+            eprintln!("{}", USAGE);
+            process::exit(1);
+        }
+    };
+
+    let tape = fs::read_to_string(path)
+        .map_err(|error| error.to_string())
+        .and_then(|text| parse_tape(&text).map_err(|error| error.to_string()))
+        .unwrap_or_else(|error| fail(&format!("Failed to read tape {}", path), error));
+
+    let mut session = Session {
+        machine: AsciiMachine::new(IntcodeMachine::new(tape)),
+        script: vec![],
+        transcript: None,
+        record: None,
+    };
+
+    let mut options = arguments[1..].iter();
+    while let Some(option) = options.next() {
+        let file = options.next().unwrap_or_else(|| {
+            eprintln!("{}", USAGE);
+            process::exit(1);
+        });
+        match option.as_str() {
+            "--script" => {
+                let script = fs::read_to_string(file)
+                    .unwrap_or_else(|error| fail(&format!("Failed to read script {}", file), error));
+                session.script = script.lines().map(str::to_string).collect();
+            }
+            "--transcript" => session.transcript = Some(create(file)),
+            "--record" => session.record = Some(create(file)),
+            _ => {
+                eprintln!("Unknown option {}\n{}", option, USAGE);
+                process::exit(1);
+            }
+        }
+    }
+
+    let result = session.run();
+    if let Err(error) = session.finish(result) {
+        eprintln!("{}", error);
+        process::exit(1);
+    }
+}
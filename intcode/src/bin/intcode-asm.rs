@@ -0,0 +1,28 @@
+use std::env;
+use std::fs;
+use std::process;
+
+use intcode::assemble;
+
+fn main() {
+    let path = env::args().nth(1).unwrap_or_else(|| {
+        eprintln!("Usage: intcode-asm <source>");
+        process::exit(1);
+    });
+
+    let source = fs::read_to_string(&path).unwrap_or_else(|error| {
+        eprintln!("Failed to read source {}: {}", path, error);
+        process::exit(1);
+    });
+
+    match assemble(&source) {
+        Ok(tape) => {
+            let tape: Vec<String> = tape.iter().map(i64::to_string).collect();
+            println!("{}", tape.join(","));
+        }
+        Err(error) => {
+            eprintln!("{}:{}", path, error);
+            process::exit(1);
+        }
+    }
+}
@@ -0,0 +1,37 @@
+use std::env;
+use std::fs;
+use std::process;
+
+use intcode::{analyze, parse_tape};
+
+const USAGE: &str = "\
+Usage: intcode-cfg <tape> [--dot]
+Prints the calls, indirect jumps and writes to code found in a tape,
+or its control flow graph in Graphviz DOT format with --dot.";
+
+fn main() {
+    let arguments: Vec<String> = env::args().skip(1).collect();
+    let (path, dot) = match arguments.as_slice() {
+        [path] => (path, false),
+        [path, option] if option == "--dot" => (path, true),
+        _ => {
+            eprintln!("{}", USAGE);
+            process::exit(1);
+        }
+    };
+
+    let tape = fs::read_to_string(path)
+        .map_err(|error| error.to_string())
+        .and_then(|text| parse_tape(&text).map_err(|error| error.to_string()))
+        .unwrap_or_else(|error| {
+            eprintln!("Failed to read tape {}: {}", path, error);
+            process::exit(1);
+        });
+
+    let graph = analyze(&tape);
+    if dot {
+        print!("{}", graph.to_dot());
+    } else {
+        print!("{}", graph);
+    }
+}
@@ -0,0 +1,22 @@
+use std::env;
+use std::fs;
+use std::process;
+
+use intcode::{compile, parse_tape};
+
+fn main() {
+    let path = env::args().nth(1).unwrap_or_else(|| {
+        eprintln!("Usage: intcode-compile <tape>");
+        process::exit(1);
+    });
+
+    let tape = fs::read_to_string(&path)
+        .map_err(|error| error.to_string())
+        .and_then(|text| parse_tape(&text).map_err(|error| error.to_string()))
+        .unwrap_or_else(|error| {
+            eprintln!("Failed to read tape {}: {}", path, error);
+            process::exit(1);
+        });
+
+    print!("{}", compile(&tape));
+}
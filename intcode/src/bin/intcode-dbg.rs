@@ -0,0 +1,272 @@
+use std::env;
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::process;
+use std::str::FromStr;
+
+use intcode::{parse_tape, Access, Instruction, IntcodeMachine, MachineStatus, Snapshot, Step};
+
+const HELP: &str = "\
+Commands:
+  s, step [n]             execute n instructions, 1 by default
+  back [n]                undo the last n instructions, 1 by default
+  c, continue             run until a breakpoint or watchpoint is hit, input is needed or the machine halts
+  b, break <addr>         stop before executing the instruction at addr
+  d, delete <addr>        remove a breakpoint
+  w, watch <addr>         stop after any instruction that reads or writes addr
+  u, unwatch <addr>       stop watching addr
+  r, regs                 print the instruction pointer, relative base, status and queued input
+  x, mem <addr> [count]   dump count cells starting at addr, 16 by default
+  l, list [addr] [count]  disassemble count instructions from addr, the current position and 10 by default
+  i, input <value>...     queue input values
+  o, output               print and clear the output produced so far
+  save <file>             save the machine state to file
+  load <file>             resume from a state saved with save, keeping breakpoints and watchpoints
+  h, help                 print this message
+  q, quit                 exit the debugger
+An empty line repeats the last command.";
+
+/// How many instructions can be undone with the back command.
+const HISTORY_LIMIT: usize = 1_000_000;
+
+struct Debugger {
+    machine: IntcodeMachine,
+    /// Output not yet shown with the `output` command.
+    output: Vec<i64>,
+}
+
+enum Flow {
+    Continue,
+    Quit,
+}
+
+fn argument<T: FromStr>(arguments: &[&str], index: usize, default: Option<T>) -> Result<T, String> {
+    match (arguments.get(index), default) {
+        (Some(argument), _) => argument.parse().map_err(|_| format!("Invalid argument {}", argument)),
+        (None, Some(default)) => Ok(default),
+        (None, None) => Err("Missing argument, type help for usage".to_string()),
+    }
+}
+
+impl Debugger {
+    fn command(&mut self, line: &str) -> Result<Flow, String> {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let (command, arguments) = match words.split_first() {
+            Some((command, arguments)) => (*command, arguments),
+            None => return Ok(Flow::Continue),
+        };
+
+        match command {
+            "s" | "step" => self.step(argument(arguments, 0, Some(1))?)?,
+            "back" => {
+                let count = argument(arguments, 0, Some(1))?;
+                let undone = self.machine.rewind(count);
+                if undone < count {
+                    println!("Reached the start of the history after {} instructions", undone);
+                }
+                self.print_listing(self.machine.position(), 1);
+            }
+            "c" | "continue" => self.resume()?,
+            "b" | "break" => self.machine.add_breakpoint(argument(arguments, 0, None)?),
+            "d" | "delete" => {
+                let address = argument(arguments, 0, None)?;
+                if !self.machine.remove_breakpoint(address) {
+                    return Err(format!("No breakpoint at {}", address));
+                }
+            }
+            "w" | "watch" => self.machine.add_watchpoint(argument(arguments, 0, None)?),
+            "u" | "unwatch" => {
+                let address = argument(arguments, 0, None)?;
+                if !self.machine.remove_watchpoint(address) {
+                    return Err(format!("Not watching {}", address));
+                }
+            }
+            "r" | "regs" => self.print_registers(),
+            "x" | "mem" => self.print_memory(argument(arguments, 0, None)?, argument(arguments, 1, Some(16))?),
+            "l" | "list" => {
+                let position = self.machine.position();
+                self.print_listing(argument(arguments, 0, Some(position))?, argument(arguments, 1, Some(10))?)
+            }
+            "i" | "input" => {
+                if arguments.is_empty() {
+                    return Err("Missing argument, type help for usage".to_string());
+                }
+                let values = (0..arguments.len())
+                    .map(|index| argument(arguments, index, None))
+                    .collect::<Result<Vec<i64>, String>>()?;
+                values.into_iter().for_each(|value| self.machine.add_input(value));
+            }
+            "o" | "output" => {
+                let output: Vec<String> = self.output.drain(..).map(|value| value.to_string()).collect();
+                println!("{}", output.join(","));
+            }
+            "save" => {
+                let path = argument::<String>(arguments, 0, None)?;
+                self.machine.save_to(&path).map_err(|error| format!("Failed to save {}: {}", path, error))?;
+            }
+            "load" => {
+                let path = argument::<String>(arguments, 0, None)?;
+                let snapshot = fs::File::open(&path)
+                    .map_err(|error| error.to_string())
+                    .and_then(|mut file| Snapshot::read_from(&mut file).map_err(|error| error.to_string()))
+                    .map_err(|error| format!("Failed to load {}: {}", path, error))?;
+                self.machine.restore(&snapshot);
+                self.print_stop();
+            }
+            "h" | "help" => println!("{}", HELP),
+            "q" | "quit" => return Ok(Flow::Quit),
+            _ => return Err(format!("Unknown command {}, type help for usage", command)),
+        }
+
+        Ok(Flow::Continue)
+    }
+
+    fn step(&mut self, count: usize) -> Result<(), String> {
+        for _ in 0..count {
+            let step = self.machine.step().map_err(|error| error.to_string())?;
+            self.print_step(&step);
+            self.output.extend(step.output);
+
+            if step.status != MachineStatus::Run {
+                break;
+            }
+        }
+
+        self.print_stop();
+        Ok(())
+    }
+
+    fn resume(&mut self) -> Result<(), String> {
+        let output = self.machine.try_run().map_err(|error| error.to_string())?;
+        self.output.extend(output);
+
+        self.print_stop();
+        Ok(())
+    }
+
+    fn print_step(&self, step: &Step) {
+        let mut effects = vec![];
+        effects.extend(step.input.map(|input| format!("in {}", input)));
+        effects.extend(step.writes.iter().map(|write| format!("[{}] {} -> {}", write.address, write.old, write.new)));
+        effects.extend(step.output.map(|output| format!("out {}", output)));
+
+        println!("{:>8}: {:<32} {}", step.instruction.address, step.instruction.to_string(), effects.join(", "));
+    }
+
+    fn print_stop(&self) {
+        let position = self.machine.position();
+        match self.machine.status() {
+            MachineStatus::Halt => println!("Halted at {}", position),
+            MachineStatus::Yield => println!("Waiting for input at {}", position),
+            MachineStatus::Breakpoint => println!("Breakpoint at {}", position),
+            MachineStatus::OutOfBudget => println!("Instruction limit reached at {}", position),
+            MachineStatus::Loop => println!("Infinite loop detected at {}", position),
+            MachineStatus::Watchpoint => {
+                for hit in self.machine.watch_hits() {
+                    match hit.access {
+                        Access::Read => println!("Watch [{}]: read {} at {}", hit.address, hit.old, hit.position),
+                        Access::Write => println!(
+                            "Watch [{}]: {} -> {} at {}", hit.address, hit.old, hit.new, hit.position
+                        ),
+                    }
+                }
+            }
+            MachineStatus::Run => {}
+        }
+    }
+
+    fn print_registers(&self) {
+        let input: Vec<String> = self.machine.pending_input().map(|value| value.to_string()).collect();
+
+        println!("position      {}", self.machine.position());
+        println!("relative base {}", self.machine.relative_base());
+        println!("status        {:?}", self.machine.status());
+        println!("input         {}", input.join(","));
+    }
+
+    fn print_memory(&self, start: usize, count: usize) {
+        for row in (start..start + count).step_by(8) {
+            let values: Vec<String> = (row..(row + 8).min(start + count))
+                .map(|address| format!("{:>8}", self.machine.peek(address)))
+                .collect();
+            println!("{:>8}: {}", row, values.join(" "));
+        }
+    }
+
+    fn print_listing(&self, start: usize, count: usize) {
+        let tape = self.machine.tape();
+        let mut address = start;
+
+        for _ in 0..count {
+            if address >= tape.len() {
+                break;
+            }
+
+            let marker = if address == self.machine.position() { ">" } else { " " };
+            let breakpoint = if self.machine.breakpoints().any(|breakpoint| breakpoint == address) { "*" } else { " " };
+
+            match Instruction::decode(tape, address) {
+                Some(instruction) => {
+                    println!("{}{}{:>7}: {}", marker, breakpoint, address, instruction);
+                    address = instruction.next();
+                }
+                None => {
+                    println!("{}{}{:>7}: .data {}", marker, breakpoint, address, tape[address]);
+                    address += 1;
+                }
+            }
+        }
+    }
+}
+
+fn main() {
+    let mut arguments = env::args().skip(1);
+    let path = arguments.next().unwrap_or_else(|| {
+        eprintln!("Usage: intcode-dbg <tape> [input...]");
+        process::exit(1);
+    });
+
+    let tape = fs::read_to_string(&path)
+        .map_err(|error| error.to_string())
+        .and_then(|text| parse_tape(&text).map_err(|error| error.to_string()))
+        .unwrap_or_else(|error| {
+            eprintln!("Failed to read tape {}: {}", path, error);
+            process::exit(1);
+        });
+
+    let mut machine = IntcodeMachine::new(tape);
+    machine.enable_history(HISTORY_LIMIT);
+    for input in arguments {
+        match input.parse() {
+            Ok(input) => machine.add_input(input),
+            Err(_) => {
+                eprintln!("Invalid input value {}", input);
+                process::exit(1);
+            }
+        }
+    }
+
+    let mut debugger = Debugger { machine, output: vec![] };
+    let stdin = io::stdin();
+    let mut lines = stdin.lock().lines();
+    let mut last = String::new();
+
+    loop {
+        print!("(intcode) ");
+        io::stdout().flush().expect("Failed to write to stdout");
+
+        let line = match lines.next() {
+            Some(Ok(line)) => line,
+            _ => break,
+        };
+        if !line.trim().is_empty() {
+            last = line;
+        }
+
+        match debugger.command(&last) {
+            Ok(Flow::Continue) => {}
+            Ok(Flow::Quit) => break,
+            Err(error) => println!("{}", error),
+        }
+    }
+}
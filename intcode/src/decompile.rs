@@ -0,0 +1,598 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+
+use crate::analysis::{analyze, Call, ControlFlowGraph, EdgeKind};
+use crate::disasm::constant_result;
+use crate::instruction::{Instruction, Opcode, Operand, ParameterMode};
+
+/// The innermost loop around the code being written, which `continue` and `break` refer to.
+#[derive(Debug, Clone, Copy)]
+struct Loop {
+    header: usize,
+    exit: Option<usize>,
+}
+
+/// The blocks reachable from an entry point without following calls.
+struct Function {
+    entry: usize,
+    blocks: Vec<usize>,
+    /// How far the relative base moved from its value on entry at the start of each block, if known.
+    deltas: BTreeMap<usize, Option<i64>>,
+    /// How far the entry moves the relative base: the frame of a function, or the stack set up by the program.
+    base: i64,
+    frame: Option<i64>,
+}
+
+enum Line {
+    Label(usize, usize),
+    Code(usize, String),
+}
+
+struct Decompiler<'a> {
+    graph: &'a ControlFlowGraph,
+    /// Calls by address of their jump.
+    calls: BTreeMap<usize, Call>,
+    /// Blocks jumping or falling into each block, calls left out.
+    predecessors: BTreeMap<usize, Vec<usize>>,
+    writes_code: BTreeSet<usize>,
+    lines: Vec<Line>,
+    gotos: BTreeSet<usize>,
+}
+
+/// The relative base after `instruction`, given its value before. Unknown once it overflows.
+fn after(delta: Option<i64>, instruction: &Instruction) -> Option<i64> {
+    match (instruction.opcode, instruction.operands.first()) {
+        (Opcode::Rel, Some(&Operand { mode: ParameterMode::Immediate, value })) => delta.and_then(|delta| delta.checked_add(value)),
+        (Opcode::Rel, _) => None,
+        _ => delta,
+    }
+}
+
+/// The amount a `rel` with an immediate parameter moves the base by.
+fn rel_amount(instruction: &Instruction) -> Option<i64> {
+    match (instruction.opcode, instruction.operands.first()) {
+        (Opcode::Rel, Some(&Operand { mode: ParameterMode::Immediate, value })) => Some(value),
+        _ => None,
+    }
+}
+
+/// Name of the function starting at `entry`.
+fn name(entry: usize) -> String {
+    if entry == 0 { "main".to_string() } else { format!("f{}", entry) }
+}
+
+/// The cell a writing instruction stores to, if it is addressed relative to the base.
+fn relative_dest(instruction: &Instruction) -> Option<&Operand> {
+    instruction.operands.last().filter(|dest| instruction.opcode.writes() && dest.mode == ParameterMode::Relative)
+}
+
+/// The instruction storing the return address of a call, among the instructions of the block making it.
+/// The function is called with its base on that cell, the arguments following it.
+fn return_store<'b>(instructions: &'b [Instruction], call: &Call) -> Option<&'b Instruction> {
+    instructions.iter().find(|instruction| constant_result(instruction) == Some(call.return_site as i64))
+}
+
+/// The conditions under which a jump is taken and not taken.
+fn conditions(jump: &Instruction, condition: &str) -> (String, String) {
+    let (taken, not_taken) = if jump.opcode == Opcode::Jnz { ("!=", "==") } else { ("==", "!=") };
+    (format!("{} {} 0", condition, taken), format!("{} {} 0", condition, not_taken))
+}
+
+impl<'a> Decompiler<'a> {
+    fn function(&self, entry: usize) -> Function {
+        let mut deltas = BTreeMap::new();
+        deltas.insert(entry, Some(0));
+        let mut pending = vec![entry];
+
+        while let Some(start) = pending.pop() {
+            let block = match self.graph.blocks.get(&start) {
+                Some(block) => block,
+                None => continue,
+            };
+            let end = block.instructions.iter().fold(deltas[&start], after);
+
+            for edge in self.graph.edges.iter().filter(|edge| edge.from == start && edge.kind != EdgeKind::Call) {
+                let merged = match deltas.get(&edge.to) {
+                    None => end,
+                    Some(&delta) if delta == end => continue,
+                    Some(_) => None,
+                };
+                if deltas.insert(edge.to, merged) != Some(merged) {
+                    pending.push(edge.to);
+                }
+            }
+        }
+
+        let base = self.graph.blocks.get(&entry)
+            .and_then(|block| rel_amount(&block.instructions[0]))
+            .unwrap_or(0);
+        let frame = Some(base).filter(|_| entry != 0 && base != 0);
+        let blocks = deltas.keys().copied().filter(|start| self.graph.blocks.contains_key(start)).collect();
+        Function { entry, blocks, deltas, base, frame }
+    }
+
+    /// Name of the cell a relative parameter refers to, counted from the base set up by the function's entry.
+    /// Inside a frame, cells below the base are locals, the cell under them the return address,
+    /// and cells above it the arguments of the functions called.
+    /// Cells too far away to be counted keep their `rb+N` name.
+    fn slot(&self, function: &Function, delta: Option<i64>, operand: &Operand) -> String {
+        let slot = match delta.and_then(|delta| delta.checked_sub(function.base)?.checked_add(operand.value)) {
+            Some(slot) => slot,
+            None => return operand.to_string(),
+        };
+
+        let local = match function.frame {
+            Some(_) if slot >= 0 => return format!("arg{}", slot),
+            Some(frame) => slot.checked_add(frame),
+            None => Some(slot),
+        };
+        match local {
+            Some(0) if function.frame.is_some() => "ret".to_string(),
+            Some(local) if local >= 0 => format!("local{}", local),
+            Some(local) => format!("outer{}", local.unsigned_abs()),
+            None => operand.to_string(),
+        }
+    }
+
+    fn value(&self, function: &Function, delta: Option<i64>, operand: &Operand) -> String {
+        match operand.mode {
+            ParameterMode::Positional => format!("mem[{}]", operand.value),
+            ParameterMode::Immediate => operand.value.to_string(),
+            ParameterMode::Relative => self.slot(function, delta, operand),
+        }
+    }
+
+    /// The value stored by a writing instruction.
+    fn expression(&self, function: &Function, delta: Option<i64>, instruction: &Instruction) -> String {
+        if instruction.opcode == Opcode::St {
+            return "input()".to_string();
+        }
+
+        let operands = &instruction.operands;
+        let (a, b) = (self.value(function, delta, &operands[0]), self.value(function, delta, &operands[1]));
+        let immediate = |index: usize| Some(operands[index].value).filter(|_| operands[index].mode == ParameterMode::Immediate);
+        match (instruction.opcode, immediate(0), immediate(1)) {
+            (Opcode::Add, Some(0), _) => b,
+            (Opcode::Add, _, Some(0)) => a,
+            (Opcode::Add, _, Some(value)) if value < 0 => format!("{} - {}", a, value.unsigned_abs()),
+            (Opcode::Add, _, _) => format!("{} + {}", a, b),
+            (Opcode::Mul, Some(1), _) => b,
+            (Opcode::Mul, _, Some(1)) => a,
+            (Opcode::Mul, _, Some(-1)) => format!("-{}", a),
+            (Opcode::Mul, _, _) => format!("{} * {}", a, b),
+            (Opcode::Tlt, _, _) => format!("{} < {}", a, b),
+            _ => format!("{} == {}", a, b),
+        }
+    }
+
+    fn emit(&mut self, indent: usize, line: String) {
+        self.lines.push(Line::Code(indent, line));
+    }
+
+    /// The statement moving control to `target`, when it does not simply run next.
+    fn jump_to(&mut self, target: usize, next: Option<usize>, context: Option<Loop>) -> Option<String> {
+        if next == Some(target) {
+            return None;
+        }
+        match context {
+            Some(context) if context.header == target => Some("continue;".to_string()),
+            Some(context) if context.exit == Some(target) => Some("break;".to_string()),
+            _ => {
+                self.gotos.insert(target);
+                Some(format!("goto L{};", target))
+            }
+        }
+    }
+
+    /// Writes the statements of a block, leaving out its final jump and the bookkeeping of calls and frames.
+    fn statements(&mut self, function: &Function, start: usize, indent: usize) {
+        self.lines.push(Line::Label(indent, start));
+        let instructions = &self.graph.blocks[&start].instructions;
+        let last = instructions.last().unwrap();
+        let call = self.calls.get(&last.address).copied();
+
+        let mut hidden = BTreeSet::new();
+        if start == function.entry && rel_amount(&instructions[0]).is_some() {
+            hidden.insert(instructions[0].address);
+        }
+        if self.graph.returns.contains(&last.address) && instructions.len() > 1 {
+            let epilogue = &instructions[instructions.len() - 2];
+            if rel_amount(epilogue).is_some() {
+                hidden.insert(epilogue.address);
+            }
+        }
+
+        let store = call.and_then(|call| return_store(instructions, &call));
+        hidden.extend(store.map(|store| store.address));
+        let return_slot = store.and_then(relative_dest);
+
+        let mut arguments = BTreeMap::new();
+        let mut delta = function.deltas[&start];
+        for instruction in instructions {
+            let before = delta;
+            delta = after(delta, instruction);
+            if instruction.opcode.is_jump() || instruction.opcode == Opcode::Halt || hidden.contains(&instruction.address) {
+                continue;
+            }
+
+            if let (Some(dest), Some(return_slot)) = (relative_dest(instruction), return_slot) {
+                if let Some(offset) = dest.value.checked_sub(return_slot.value).filter(|&offset| offset > 0) {
+                    arguments.insert(offset, self.slot(function, before, dest));
+                }
+            }
+
+            let dest = instruction.operands.last().filter(|_| instruction.opcode.writes());
+
+            let mut line = match (instruction.opcode, dest) {
+                (Opcode::Ld, _) => format!("output({});", self.value(function, before, &instruction.operands[0])),
+                (Opcode::Rel, _) => format!("rb += {};", self.value(function, before, &instruction.operands[0])),
+                (_, Some(dest)) => {
+                    let name = match dest.mode {
+                        ParameterMode::Positional => format!("mem[{}]", dest.value),
+                        _ => self.slot(function, before, dest),
+                    };
+                    format!("{} = {};", name, self.expression(function, before, instruction))
+                }
+                _ => unreachable!("every other instruction writes"),
+            };
+            if self.writes_code.contains(&instruction.address) {
+                line.push_str(" // self-modifying");
+            }
+            self.emit(indent, line);
+        }
+
+        if let Some(call) = call {
+            let arguments: Vec<String> = arguments.into_values().collect();
+            self.emit(indent, format!("{}({});", name(call.function), arguments.join(", ")));
+        }
+    }
+
+    /// Writes how a block ends: its final jump, or the flow into the next block.
+    fn terminator(&mut self, function: &Function, start: usize, next: Option<usize>, context: Option<Loop>, indent: usize) {
+        let instructions = &self.graph.blocks[&start].instructions;
+        let last = instructions.last().unwrap();
+        let delta = function.deltas[&start].and_then(|delta| {
+            instructions[..instructions.len() - 1].iter().try_fold(delta, |delta, instruction| after(Some(delta), instruction))
+        });
+
+        let mut lines = vec![];
+        if last.opcode == Opcode::Halt {
+            lines.push("halt;".to_string());
+        } else if self.graph.returns.contains(&last.address) {
+            lines.push("return;".to_string());
+        } else if let Some(call) = self.calls.get(&last.address).copied() {
+            lines.extend(self.jump_to(call.return_site, next, context));
+        } else if last.opcode.is_jump() {
+            let condition = self.value(function, delta, &last.operands[0]);
+            let (taken, not_taken) = conditions(last, &condition);
+            let target = match last.jump_target() {
+                Some(target) => self.jump_to(target, next, context),
+                None => Some(format!("jump {};", self.value(function, delta, &last.operands[1]))),
+            };
+
+            if !last.falls_through() {
+                lines.extend(target);
+            } else {
+                let fall = self.jump_to(last.next(), next, context);
+                match (target, fall) {
+                    (Some(target), fall) => {
+                        lines.push(format!("if {} {{ {} }}", taken, target));
+                        lines.extend(fall);
+                    }
+                    (None, Some(fall)) => lines.push(format!("if {} {{ {} }}", not_taken, fall)),
+                    (None, None) => {}
+                }
+            }
+        } else {
+            lines.extend(self.jump_to(last.next(), next, context));
+        }
+
+        for line in lines {
+            self.emit(indent, line);
+        }
+    }
+
+    /// Whether every block in `blocks[targets]` is only entered from `blocks[sources]`.
+    fn entered_only_from(&self, function: &Function, targets: (usize, usize), sources: (usize, usize)) -> bool {
+        let sources = &function.blocks[sources.0..sources.1];
+        function.blocks[targets.0..targets.1].iter().all(|block| {
+            self.predecessors.get(block).is_none_or(|from| from.iter().all(|from| sources.binary_search(from).is_ok()))
+        })
+    }
+
+    /// Index of the block at `address` in `blocks[low..high]`, or `high` if `address` is where the region continues.
+    fn index(function: &Function, low: usize, high: usize, follow: Option<usize>, address: usize) -> Option<usize> {
+        if follow == Some(address) {
+            return Some(high);
+        }
+        function.blocks[low..high].binary_search(&address).ok().map(|index| low + index)
+    }
+
+    /// Writes `blocks[low..high]`, which continue with `follow`, as structured code.
+    fn region(&mut self, function: &Function, range: (usize, usize), follow: Option<usize>, context: Option<Loop>, indent: usize) {
+        let (low, high) = range;
+        let next = |index: usize| if index < high { Some(function.blocks[index]) } else { follow };
+
+        let mut i = low;
+        while i < high {
+            let start = function.blocks[i];
+
+            // A loop runs from its header to the last block jumping back to it, when nothing jumps into its middle.
+            let back = (i..high).rev().find(|&k| {
+                self.graph.edges.iter().any(|edge| edge.from == function.blocks[k] && edge.to == start && edge.kind == EdgeKind::Jump)
+            });
+            if let Some(end) = back.filter(|_| context.is_none_or(|context| context.header != start || i != low)) {
+                if self.entered_only_from(function, (i + 1, end + 1), (i, end + 1)) {
+                    self.emit(indent, "loop {".to_string());
+                    let inner = Loop { header: start, exit: next(end + 1) };
+                    self.region(function, (i, end + 1), Some(start), Some(inner), indent + 1);
+                    self.emit(indent, "}".to_string());
+                    i = end + 1;
+                    continue;
+                }
+            }
+
+            self.statements(function, start, indent);
+            match self.conditional(function, (i, high), follow) {
+                Some((then_end, else_end)) => {
+                    let last = self.graph.blocks[&start].instructions.last().unwrap().clone();
+                    let delta = function.deltas[&start].and_then(|delta| {
+                        let instructions = &self.graph.blocks[&start].instructions;
+                        instructions[..instructions.len() - 1].iter().try_fold(delta, |delta, instruction| after(Some(delta), instruction))
+                    });
+                    let condition = self.value(function, delta, &last.operands[0]);
+                    let (_, not_taken) = conditions(&last, &condition);
+
+                    let join = next(else_end);
+                    self.emit(indent, format!("if {} {{", not_taken));
+                    self.region(function, (i + 1, then_end), join, context, indent + 1);
+                    if else_end > then_end {
+                        self.emit(indent, "} else {".to_string());
+                        self.region(function, (then_end, else_end), join, context, indent + 1);
+                    }
+                    self.emit(indent, "}".to_string());
+                    i = else_end;
+                }
+                None => {
+                    self.terminator(function, start, next(i + 1), context, indent);
+                    i += 1;
+                }
+            }
+        }
+    }
+
+    /// Where the branches of an if statement end, when the block at `range.0` can start one:
+    /// it skips forward over a then branch only entered through it, which may end by jumping over an else branch.
+    fn conditional(&self, function: &Function, range: (usize, usize), follow: Option<usize>) -> Option<(usize, usize)> {
+        let (i, high) = range;
+        let start = function.blocks[i];
+        let last = self.graph.blocks[&start].instructions.last().unwrap();
+        if !last.opcode.is_jump() || !last.falls_through() || self.calls.contains_key(&last.address) {
+            return None;
+        }
+
+        let target = last.jump_target().filter(|&target| target > start)?;
+        let then_end = Self::index(function, i + 1, high, follow, target).filter(|&end| end > i + 1)?;
+        if !self.entered_only_from(function, (i + 1, then_end), (i, then_end)) {
+            return None;
+        }
+
+        let jump = self.graph.blocks[&function.blocks[then_end - 1]].instructions.last().unwrap();
+        let else_end = jump.jump_target()
+            .filter(|&join| jump.opcode.is_jump() && !jump.falls_through() && join > target && then_end < high)
+            .filter(|_| !self.calls.contains_key(&jump.address))
+            .and_then(|join| Self::index(function, then_end + 1, high, follow, join))
+            .filter(|&else_end| self.entered_only_from(function, (then_end, else_end), (i, else_end)));
+        Some((then_end, else_end.unwrap_or(then_end)))
+    }
+
+    fn write_function(&mut self, function: &Function, parameters: &BTreeSet<i64>) {
+        let parameters: Vec<String> = parameters.iter().map(|slot| format!("local{}", slot)).collect();
+        self.emit(0, format!("fn {}({}) {{", name(function.entry), parameters.join(", ")));
+        if let Some(frame) = function.frame {
+            self.emit(1, format!("// frame of {} cells", frame));
+        }
+        self.region(function, (0, function.blocks.len()), None, None, 1);
+        self.emit(0, "}".to_string());
+    }
+}
+
+/// Lifts the code of a tape into structured pseudo-code, one function for the program and one for each function it calls.
+///
+/// Loops and if/else statements are recovered where the jumps nest, with `goto` covering the rest.
+/// Cells accessed through the relative base are named after their place in the stack frame of the function:
+/// `local` for the parameters and locals, `arg` for the arguments of the functions it calls, and `ret` for its return address.
+pub fn decompile(tape: &[i64]) -> String {
+    let graph = analyze(tape);
+
+    let mut predecessors: BTreeMap<usize, Vec<usize>> = BTreeMap::new();
+    for edge in graph.edges.iter().filter(|edge| edge.kind != EdgeKind::Call) {
+        predecessors.entry(edge.to).or_default().push(edge.from);
+    }
+
+    let mut decompiler = Decompiler {
+        graph: &graph,
+        calls: graph.calls.iter().map(|call| (call.address, *call)).collect(),
+        predecessors,
+        writes_code: graph.code_writes.iter().map(|write| write.address).collect(),
+        lines: vec![],
+        gotos: BTreeSet::new(),
+    };
+
+    let entries: BTreeSet<usize> = graph.calls.iter().map(|call| call.function).chain(Some(0)).collect();
+    let functions: Vec<Function> = entries.iter().map(|&entry| decompiler.function(entry)).collect();
+
+    // Parameters are the arguments stored by every call, at the base of the function.
+    let mut parameters: BTreeMap<usize, BTreeSet<i64>> = BTreeMap::new();
+    for call in &graph.calls {
+        let instructions = &graph.blocks.range(..=call.address).next_back().unwrap().1.instructions;
+        let slots = parameters.entry(call.function).or_default();
+        if let Some(return_slot) = return_store(instructions, call).and_then(relative_dest) {
+            slots.extend(instructions.iter()
+                .filter_map(relative_dest)
+                .filter_map(|dest| dest.value.checked_sub(return_slot.value))
+                .filter(|&offset| offset > 0));
+        }
+    }
+
+    for (i, function) in functions.iter().enumerate() {
+        if i > 0 {
+            decompiler.lines.push(Line::Code(0, String::new()));
+        }
+        decompiler.write_function(function, &parameters.get(&function.entry).cloned().unwrap_or_default());
+    }
+
+    let mut source = String::new();
+    for line in &decompiler.lines {
+        match line {
+            Line::Label(indent, address) if decompiler.gotos.contains(address) => {
+                writeln!(source, "{}L{}:", "    ".repeat(indent.saturating_sub(1)), address).unwrap();
+            }
+            Line::Label(..) => {}
+            Line::Code(_, text) if text.is_empty() => writeln!(source).unwrap(),
+            Line::Code(indent, text) => writeln!(source, "{}{}", "    ".repeat(*indent), text).unwrap(),
+        }
+    }
+    source
+}
+
+#[cfg(test)]
+mod tests {
+    use super::decompile;
+    use crate::assemble;
+
+    #[test]
+    fn test_loops() {
+        let tape = assemble("
+            st [count]
+        loop:
+            ld [count]
+            add [count], #-1, [count]
+            jnz [count], #loop
+            halt
+        count: .data 0
+        ").unwrap();
+
+        assert_eq!(decompile(&tape), "\
+fn main() {
+    mem[12] = input();
+    loop {
+        output(mem[12]);
+        mem[12] = mem[12] - 1;
+        if mem[12] == 0 { break; }
+    }
+    halt;
+}
+");
+    }
+
+    #[test]
+    fn test_if_else() {
+        let tape = assemble("
+            st [x]
+            tlt [x], #10, [small]
+            jz [small], #big
+            ld #1
+            jz #0, #end
+        big:
+            ld #2
+        end:
+            halt
+        x: .data 0
+        small: .data 0
+        ").unwrap();
+
+        assert_eq!(decompile(&tape), "\
+fn main() {
+    mem[17] = input();
+    mem[18] = mem[17] < 10;
+    if mem[18] != 0 {
+        output(1);
+    } else {
+        output(2);
+    }
+    halt;
+}
+");
+    }
+
+    #[test]
+    fn test_functions_with_frames() {
+        // Sets up a stack, calls a function doubling its argument, and outputs the result it leaves in its frame.
+        let tape = assemble("
+            rel #100
+            st rb+1
+            add #call_return, #0, rb+0
+            jz #0, #double
+        call_return:
+            ld rb+1
+            halt
+        double:
+            rel #2
+            mul rb-1, #2, rb-1
+            rel #-2
+            jz #0, rb+0
+        ").unwrap();
+
+        assert_eq!(decompile(&tape), "\
+fn main() {
+    local1 = input();
+    f14(local1);
+    output(local1);
+    halt;
+}
+
+fn f14(local1) {
+    // frame of 2 cells
+    local1 = local1 * 2;
+    return;
+}
+");
+    }
+
+    #[test]
+    fn test_unstructured_jumps_use_goto() {
+        // Jumps into the middle of a loop.
+        let tape = vec![1105, 1, 5, 104, 1, 104, 2, 1105, 1, 3];
+
+        assert_eq!(decompile(&tape), "\
+fn main() {
+    goto L5;
+L3:
+    output(1);
+L5:
+    output(2);
+    goto L3;
+}
+");
+    }
+
+    #[test]
+    fn test_extreme_values() {
+        let tape = vec![1001, 5, i64::MIN, 5, 99, 0];
+        assert_eq!(decompile(&tape), "\
+fn main() {
+    mem[5] = mem[5] - 9223372036854775808;
+    halt;
+}
+");
+
+        let tape = vec![109, i64::MAX, 109, 1, 99];
+        assert_eq!(decompile(&tape), "\
+fn main() {
+    rb += 1;
+    halt;
+}
+");
+
+        // The base overflows, so the cell it points to cannot be named.
+        let tape = vec![109, i64::MAX, 109, 1, 204, 0, 99];
+        assert_eq!(decompile(&tape), "\
+fn main() {
+    rb += 1;
+    output(rb+0);
+    halt;
+}
+");
+    }
+}
@@ -0,0 +1,269 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+
+use crate::instruction::{Instruction, Opcode, ParameterMode};
+
+/// Most values printed on a single `.data` line.
+const DATA_PER_LINE: usize = 8;
+
+/// What a run of tape cells was recognised as.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum Item {
+    Instruction(Instruction),
+    Data(Vec<i64>),
+}
+
+/// A line of the disassembly, starting at `address` and optionally preceded by a label.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Entry {
+    pub address: usize,
+    pub label: Option<String>,
+    pub item: Item,
+}
+
+/// A tape split into code and data, printable as assembly.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Disassembly {
+    pub entries: Vec<Entry>,
+    pub labels: BTreeMap<usize, String>,
+}
+
+/// Disassembles a tape.
+/// Code is found by following execution from address 0 through fall-throughs and immediate jump targets,
+/// everything that is never reached is printed as data. Jump destinations get a label.
+pub fn disassemble(tape: &[i64]) -> Disassembly {
+    let code = find_code(tape);
+
+    let labels: BTreeMap<usize, String> = code.values()
+        .filter_map(Instruction::jump_target)
+        .filter(|&target| target < tape.len() && is_boundary(&code, target))
+        .map(|target| (target, format!("L{}", target)))
+        .collect();
+
+    let mut entries = vec![];
+    let mut address = 0;
+    while address < tape.len() {
+        let label = labels.get(&address).cloned();
+
+        if let Some(instruction) = code.get(&address) {
+            entries.push(Entry { address, label, item: Item::Instruction(instruction.clone()) });
+            address = instruction.next();
+            continue;
+        }
+
+        let start = address;
+        let mut values = vec![];
+        while address < tape.len() && values.len() < DATA_PER_LINE && !code.contains_key(&address)
+            && (address == start || !labels.contains_key(&address)) {
+            values.push(tape[address]);
+            address += 1;
+        }
+        entries.push(Entry { address: start, label, item: Item::Data(values) });
+    }
+
+    Disassembly { entries, labels }
+}
+
+/// Whether `address` starts an instruction or lies outside of any, so a label can be placed there.
+fn is_boundary(code: &BTreeMap<usize, Instruction>, address: usize) -> bool {
+    match code.range(..=address).next_back() {
+        Some((&start, instruction)) => start == address || instruction.next() <= address,
+        None => true,
+    }
+}
+
+/// Finds every instruction reachable from address 0.
+///
+/// Besides fall-throughs and immediate jump targets, the cell after an unconditional jump is also
+/// followed when the program computes its address as a constant somewhere, which is how Intcode
+/// programs push return addresses before calling a function.
+pub(crate) fn find_code(tape: &[i64]) -> BTreeMap<usize, Instruction> {
+    let mut code: BTreeMap<usize, Instruction> = BTreeMap::new();
+    let mut covered = vec![false; tape.len()];
+    let mut visited = BTreeSet::new();
+    let mut constants = BTreeSet::new();
+    let mut pending = vec![0];
+
+    loop {
+        while let Some(address) = pending.pop() {
+            if address >= tape.len() || !visited.insert(address) {
+                continue;
+            }
+
+            let instruction = match Instruction::decode(tape, address) {
+                Some(instruction) => instruction,
+                None => continue,
+            };
+
+            if covered[address..instruction.next()].iter().any(|&cell| cell) {
+                continue;
+            }
+            covered[address..instruction.next()].iter_mut().for_each(|cell| *cell = true);
+
+            if instruction.falls_through() {
+                pending.push(instruction.next());
+            }
+            if let Some(target) = instruction.jump_target() {
+                pending.push(target);
+            }
+            if let Some(constant) = constant_result(&instruction) {
+                constants.insert(constant);
+            }
+
+            code.insert(address, instruction);
+        }
+
+        let return_sites: Vec<usize> = code.values()
+            .filter(|instruction| instruction.opcode != Opcode::Halt && !instruction.falls_through())
+            .map(Instruction::next)
+            .filter(|&address| address < tape.len() && !visited.contains(&address))
+            .filter(|&address| constants.contains(&(address as i64)))
+            .collect();
+
+        if return_sites.is_empty() {
+            return code;
+        }
+        pending.extend(return_sites);
+    }
+}
+
+/// The value an `add` or `mul` stores when both of its inputs are immediates.
+pub(crate) fn constant_result(instruction: &Instruction) -> Option<i64> {
+    let operands = &instruction.operands;
+    if operands.len() != 3 || operands[..2].iter().any(|operand| operand.mode != ParameterMode::Immediate) {
+        return None;
+    }
+
+    match instruction.opcode {
+        Opcode::Add => operands[0].value.checked_add(operands[1].value),
+        Opcode::Mul => operands[0].value.checked_mul(operands[1].value),
+        _ => None,
+    }
+}
+
+impl Disassembly {
+    /// Formats an instruction, replacing immediate jump targets with their label.
+    fn format_instruction(&self, instruction: &Instruction) -> String {
+        let mut text = instruction.opcode.mnemonic().to_string();
+
+        for (i, operand) in instruction.operands.iter().enumerate() {
+            text.push_str(if i == 0 { " " } else { ", " });
+
+            let label = instruction.jump_target()
+                .filter(|_| i == 1)
+                .and_then(|target| self.labels.get(&target));
+
+            match label {
+                Some(label) => text.push_str(&format!("#{}", label)),
+                None => text.push_str(&operand.to_string()),
+            }
+        }
+
+        text
+    }
+}
+
+impl fmt::Display for Disassembly {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for entry in &self.entries {
+            if let Some(label) = &entry.label {
+                writeln!(f, "{}:", label)?;
+            }
+
+            let text = match &entry.item {
+                Item::Instruction(instruction) => self.format_instruction(instruction),
+                Item::Data(values) => {
+                    let values: Vec<String> = values.iter().map(i64::to_string).collect();
+                    format!(".data {}", values.join(", "))
+                }
+            };
+
+            writeln!(f, "    {:<40} ; {}", text, entry.address)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{disassemble, Item};
+
+    #[test]
+    fn test_disassemble_straight_line_code() {
+        let tape: Vec<i64> = vec![1, 9, 10, 3, 2, 3, 11, 0, 99, 30, 40, 50];
+        let disassembly = disassemble(&tape);
+
+        assert_eq!(disassembly.to_string(), "    \
+add [9], [10], [3]                       ; 0
+    mul [3], [11], [0]                       ; 4
+    halt                                     ; 8
+    .data 30, 40, 50                         ; 9
+");
+    }
+
+    #[test]
+    fn test_disassemble_modes() {
+        let tape: Vec<i64> = vec![109, -3, 21101, 5, 7, 3, 204, 2, 99];
+        let disassembly = disassemble(&tape);
+
+        let lines: Vec<String> = disassembly.to_string().lines().map(|line| line.trim().to_string()).collect();
+        assert!(lines[0].starts_with("rel #-3"));
+        assert!(lines[1].starts_with("add #5, #7, rb+3"));
+        assert!(lines[2].starts_with("ld rb+2"));
+    }
+
+    #[test]
+    fn test_disassemble_extreme_offsets() {
+        let tape: Vec<i64> = vec![204, i64::MIN, 204, i64::MAX, 99];
+        let disassembly = disassemble(&tape).to_string();
+
+        assert!(disassembly.contains("ld rb-9223372036854775808"));
+        assert!(disassembly.contains("ld rb+9223372036854775807"));
+    }
+
+    #[test]
+    fn test_disassemble_labels_jump_targets() {
+        let tape: Vec<i64> = vec![3, 3, 1105, -1, 9, 1101, 0, 0, 12, 4, 12, 99, 1];
+        let disassembly = disassemble(&tape);
+
+        assert_eq!(disassembly.labels.get(&9), Some(&"L9".to_string()));
+        assert!(disassembly.to_string().contains("jnz #-1, #L9"));
+        assert!(disassembly.to_string().contains("L9:\n    ld [12]"));
+    }
+
+    #[test]
+    fn test_unreachable_code_is_data() {
+        let tape: Vec<i64> = vec![1105, 1, 7, 1, 0, 0, 0, 99];
+        let disassembly = disassemble(&tape);
+
+        let items: Vec<&Item> = disassembly.entries.iter().map(|entry| &entry.item).collect();
+        assert_eq!(items[1], &Item::Data(vec![1, 0, 0, 0]));
+        assert_eq!(disassembly.entries[2].label, Some("L7".to_string()));
+    }
+
+    #[test]
+    fn test_return_sites_after_calls_are_code() {
+        // A call to a function at 10 that pushes its return address (7) on the stack first.
+        let tape: Vec<i64> = vec![
+            21101, 7, 0, 0, 1105, 1, 10,
+            104, 1, 99,
+            104, 2, 2106, 0, 0,
+        ];
+        let disassembly = disassemble(&tape);
+
+        let code: Vec<usize> = disassembly.entries.iter()
+            .filter(|entry| matches!(entry.item, Item::Instruction(_)))
+            .map(|entry| entry.address)
+            .collect();
+        assert_eq!(code, vec![0, 4, 7, 9, 10, 12]);
+    }
+
+    #[test]
+    fn test_return_site_past_the_end() {
+        // Computes the address right after the final jump.
+        let tape: Vec<i64> = vec![1102, 7, 1, 8, 1105, 1, 0];
+        let disassembly = disassemble(&tape);
+
+        assert_eq!(disassembly.entries.len(), 2);
+    }
+}
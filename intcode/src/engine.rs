@@ -0,0 +1,338 @@
+use crate::instruction::{Opcode, ParameterMode};
+use crate::{Input, IntcodeError, IntcodeMachine, MachineStatus, Memory, Output};
+
+/// How `run` executes instructions.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Engine {
+    /// Decodes every instruction each time it executes. The reference implementation.
+    Interpreter,
+    /// Decodes each instruction once and keeps it until the program overwrites one of its cells.
+    /// Falls back to the interpreter while tracing, profiling, recording history, watching cells
+    /// or running a custom instruction set.
+    Decoded,
+}
+
+/// An instruction parameter with its mode resolved.
+#[derive(Debug, Clone, Copy)]
+enum Arg {
+    Position(i64),
+    Immediate(i64),
+    Relative(i64),
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Op {
+    Add(Arg, Arg, Arg),
+    Mul(Arg, Arg, Arg),
+    St(Arg),
+    Ld(Arg),
+    Jnz(Arg, Arg),
+    Jz(Arg, Arg),
+    Tlt(Arg, Arg, Arg),
+    Teq(Arg, Arg, Arg),
+    Rel(Arg),
+    Halt,
+}
+
+/// Instructions past this address are decoded every time they execute, so that code running far into
+/// a sparse memory does not make the cache allocate an entry for every address before it.
+const MAX_CACHED_ADDRESS: usize = 1 << 20;
+
+/// Decoded instructions by address, up to the last instruction executed.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct DecodeCache {
+    ops: Vec<Option<Op>>,
+}
+
+impl DecodeCache {
+    /// Forgets every instruction that covers `address`, which was just overwritten.
+    fn invalidate(&mut self, address: usize) {
+        let start = address.saturating_sub(3);
+        let end = (address + 1).min(self.ops.len());
+        if start < end {
+            self.ops[start..end].iter_mut().for_each(|op| *op = None);
+        }
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.ops.clear();
+    }
+}
+
+impl<I: Input, O: Output, M: Memory> IntcodeMachine<I, O, M> {
+    pub fn set_engine(&mut self, engine: Engine) {
+        self.engine = engine;
+        self.cache.clear();
+    }
+
+    pub fn with_engine(mut self, engine: Engine) -> Self {
+        self.set_engine(engine);
+        self
+    }
+
+    pub fn engine(&self) -> Engine {
+        self.engine
+    }
+
+    /// Keeps the decoded instructions consistent with a write to the tape.
+    pub(crate) fn invalidate(&mut self, address: usize) {
+        if self.engine == Engine::Decoded {
+            self.cache.invalidate(address);
+        }
+    }
+
+    /// Whether the next instruction can skip the interpreter and its instrumentation.
+    pub(crate) fn use_decoded(&self) -> bool {
+        self.engine == Engine::Decoded && !self.profiling && self.watchpoints.is_empty() && self.instruction_set.is_none()
+    }
+
+    /// Decodes the instruction under the instruction pointer. Returns `None` for anything the interpreter
+    /// has to handle: invalid instructions, which raise errors, and instructions running past the end of the tape,
+    /// which grow it.
+    fn decode_op(&self) -> Option<Op> {
+        let instruction = self.instruction();
+        let opcode = Opcode::from_code(instruction % 100)?;
+        if self.position + opcode.arity() >= self.tape.len() {
+            return None;
+        }
+
+        let mut modes = instruction / 100;
+        let mut args = [Arg::Immediate(0); 3];
+        for (offset, arg) in args.iter_mut().enumerate().take(opcode.arity()) {
+            let value = self.tape.get(self.position + offset + 1);
+            *arg = match ParameterMode::from_digit(modes % 10)? {
+                ParameterMode::Positional => Arg::Position(value),
+                ParameterMode::Immediate => Arg::Immediate(value),
+                ParameterMode::Relative => Arg::Relative(value),
+            };
+            modes /= 10;
+        }
+        if opcode.writes() {
+            if let Arg::Immediate(_) = args[opcode.arity() - 1] {
+                return None;
+            }
+        }
+
+        let [a, b, c] = args;
+        Some(match opcode {
+            Opcode::Add => Op::Add(a, b, c),
+            Opcode::Mul => Op::Mul(a, b, c),
+            Opcode::St => Op::St(a),
+            Opcode::Ld => Op::Ld(a),
+            Opcode::Jnz => Op::Jnz(a, b),
+            Opcode::Jz => Op::Jz(a, b),
+            Opcode::Tlt => Op::Tlt(a, b, c),
+            Opcode::Teq => Op::Teq(a, b, c),
+            Opcode::Rel => Op::Rel(a),
+            Opcode::Halt => Op::Halt,
+            Opcode::Custom { .. } => return None,
+        })
+    }
+
+    fn read(&mut self, arg: Arg) -> Result<i64, IntcodeError> {
+        match arg {
+            Arg::Position(address) => {
+                let address = self.address(address)?;
+                self.load(address)
+            }
+            Arg::Immediate(value) => Ok(value),
+            Arg::Relative(offset) => {
+                let address = self.relative_address(offset)?;
+                self.load(address)
+            }
+        }
+    }
+
+    fn dest(&self, arg: Arg) -> Result<usize, IntcodeError> {
+        match arg {
+            Arg::Relative(offset) => self.relative_address(offset),
+            // Writes in immediate mode are never decoded, the interpreter reports them.
+            Arg::Position(address) | Arg::Immediate(address) => self.address(address),
+        }
+    }
+
+    fn write(&mut self, arg: Arg, value: i64) -> Result<(), IntcodeError> {
+        let address = self.dest(arg)?;
+        self.store(address, value)
+    }
+
+    fn jump_if(&mut self, condition: bool, target: Arg) -> Result<(), IntcodeError> {
+        let target = self.read(target)?;
+        if condition {
+            self.jump(target)
+        } else {
+            self.position += 3;
+            Ok(())
+        }
+    }
+
+    /// Executes the instruction under the instruction pointer from the cache, decoding it first if needed.
+    /// Behaves exactly like `execute`, which it defers to for anything unusual.
+    pub(crate) fn execute_decoded(&mut self) -> Result<(), IntcodeError> {
+        let cached = self.cache.ops.get(self.position).copied().flatten();
+        let op = match cached.or_else(|| self.decode_op()) {
+            Some(op) => op,
+            None => return self.execute(),
+        };
+        if cached.is_none() && self.position < MAX_CACHED_ADDRESS {
+            if self.cache.ops.len() <= self.position {
+                self.cache.ops.resize(self.position + 1, None);
+            }
+            self.cache.ops[self.position] = Some(op);
+        }
+
+        match op {
+            Op::Add(a, b, c) => {
+                let value = self.read(a)?.checked_add(self.read(b)?).ok_or_else(|| self.overflow())?;
+                self.write(c, value)?;
+                self.position += 4;
+            }
+            Op::Mul(a, b, c) => {
+                let value = self.read(a)?.checked_mul(self.read(b)?).ok_or_else(|| self.overflow())?;
+                self.write(c, value)?;
+                self.position += 4;
+            }
+            Op::St(a) => {
+                let dest = self.dest(a)?;
+                match self.input.pop_front().or_else(|| self.source.read()) {
+                    Some(input) => {
+                        self.store(dest, input)?;
+                        self.position += 2;
+                    }
+                    None => self.status = MachineStatus::Yield,
+                }
+            }
+            Op::Ld(a) => {
+                let value = self.read(a)?;
+                self.output.write(value);
+                self.buffered_output += 1;
+                self.position += 2;
+            }
+            Op::Jnz(a, b) => {
+                let condition = self.read(a)? != 0;
+                self.jump_if(condition, b)?;
+            }
+            Op::Jz(a, b) => {
+                let condition = self.read(a)? == 0;
+                self.jump_if(condition, b)?;
+            }
+            Op::Tlt(a, b, c) => {
+                let value = (self.read(a)? < self.read(b)?) as i64;
+                self.write(c, value)?;
+                self.position += 4;
+            }
+            Op::Teq(a, b, c) => {
+                let value = (self.read(a)? == self.read(b)?) as i64;
+                self.write(c, value)?;
+                self.position += 4;
+            }
+            Op::Rel(a) => {
+                let offset = self.read(a)? as isize;
+                self.relative_base = self.relative_base.checked_add(offset).ok_or_else(|| self.overflow())?;
+                self.position += 2;
+            }
+            Op::Halt => self.status = MachineStatus::Halt,
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Engine, MAX_CACHED_ADDRESS};
+    use crate::fixtures::{compare_program, quine};
+    use crate::{assemble, IntcodeMachine, PagedMemory};
+
+    fn patching_program() -> Vec<i64> {
+        // Outputs 1, then overwrites the operand of the instruction that did it and runs it again.
+        assemble("
+        start:
+            ld #1
+            jnz [done], #end
+            add #1, #0, [done]
+            add #2, #0, [1]
+            jz #0, #start
+        end:
+            halt
+        done: .data 0
+        ").unwrap()
+    }
+
+    /// Runs `tape` with both engines, checking they end in the same state.
+    fn assert_same_run(tape: Vec<i64>, input: &[i64]) {
+        let run = |engine| {
+            let mut machine = IntcodeMachine::new(tape.clone()).with_engine(engine);
+            input.iter().for_each(|&value| machine.add_input(value));
+            let result = machine.try_run();
+            (result, machine.snapshot())
+        };
+
+        assert_eq!(run(Engine::Decoded), run(Engine::Interpreter));
+    }
+
+    #[test]
+    fn test_engines_agree() {
+        for input in 6..11 {
+            assert_same_run(compare_program(), &[input]);
+        }
+        assert_same_run(quine(), &[]);
+        assert_same_run(vec![1, 1, 1, 4, 99, 5, 6, 0, 99], &[]);
+        assert_same_run(vec![1, 9, 10, 3, 2, 3, 11, 0, 99, 30, 40, 50], &[]);
+        assert_same_run(vec![104, 1125899906842624, 99], &[]);
+        assert_same_run(vec![3, 0, 4, 0, 99], &[42]);
+        assert_same_run(vec![3, 0, 4, 0, 99], &[]);
+        assert_same_run(patching_program(), &[]);
+    }
+
+    #[test]
+    fn test_engines_agree_on_sparse_memory() {
+        // Writes far away, then jumps past the last cached address to a `ld #0` and a `halt` it wrote there.
+        let far = MAX_CACHED_ADDRESS as i64 * 4;
+        let tape = vec![1101, 1, 1, 10_000_000_000, 104, 7, 1101, 104, 0, far, 1101, 99, 0, far + 2,
+                        1105, 1, far, 99];
+        let run = |engine| {
+            let mut machine = IntcodeMachine::from_memory(PagedMemory::new(tape.clone())).with_engine(engine);
+            let result = machine.try_run();
+            (result, machine.snapshot())
+        };
+
+        let (result, snapshot) = run(Engine::Decoded);
+        assert_eq!(result, Ok(vec![7, 0]));
+        assert_eq!((result, snapshot), run(Engine::Interpreter));
+    }
+
+    #[test]
+    fn test_engines_agree_on_errors() {
+        assert_same_run(vec![1, 0, 0, 0, 42], &[]);
+        assert_same_run(vec![11101, 1, 1, 0, 99], &[]);
+        assert_same_run(vec![1, -1, 0, 0, 99], &[]);
+        assert_same_run(vec![1105, 1, 1000], &[]);
+        assert_same_run(vec![1101, 1, 1], &[]);
+        assert_same_run(vec![203, -1, 99], &[1]);
+        assert_same_run(vec![1101, i64::MAX, 1, 0, 99], &[]);
+        assert_same_run(vec![1102, i64::MIN, -1, 0, 99], &[]);
+        assert_same_run(vec![109, i64::MAX, 109, 1, 99], &[]);
+        assert_same_run(vec![109, i64::MAX, 204, 1, 99], &[]);
+    }
+
+    #[test]
+    fn test_cache_invalidated_by_self_modifying_code() {
+        let mut machine = IntcodeMachine::new(patching_program()).with_engine(Engine::Decoded);
+
+        assert_eq!(machine.run(), vec![1, 2]);
+    }
+
+    #[test]
+    fn test_decoded_engine_after_restore() {
+        let mut machine = IntcodeMachine::new(vec![3, 7, 4, 7, 1105, 1, 0, 0]).with_engine(Engine::Decoded);
+        let start = machine.snapshot();
+        machine.add_input(5);
+        assert_eq!(machine.run(), vec![5]);
+
+        let mut tape = start.clone();
+        tape.tape[0].1[2] = 104;
+        machine.restore(&tape);
+        machine.add_input(5);
+        assert_eq!(machine.run(), vec![7]);
+    }
+}
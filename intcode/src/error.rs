@@ -0,0 +1,95 @@
+use std::error::Error;
+use std::fmt;
+
+/// Errors raised while executing an Intcode program.
+/// Every variant carries the position of the faulting instruction and the raw instruction value
+/// found there, so a bad tape can be inspected without rerunning it.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum IntcodeError {
+    /// The instruction's opcode (its two lowest digits) is not part of the instruction set.
+    UnknownOpcode { position: usize, instruction: i64 },
+    /// One of the parameter mode digits is not 0 (positional), 1 (immediate) or 2 (relative).
+    InvalidParameterMode { position: usize, instruction: i64, mode: i64 },
+    /// A parameter resolved to an address below zero.
+    NegativeAddress { position: usize, instruction: i64, address: i64 },
+    /// An instruction tried to write through a parameter in immediate mode.
+    ImmediateWrite { position: usize, instruction: i64 },
+    /// A jump was taken to an address outside of the tape.
+    JumpOutOfRange { position: usize, instruction: i64, target: i64 },
+    /// Reaching an address would take more memory than the machine's memory limit allows.
+    MemoryLimit { position: usize, instruction: i64, address: usize },
+    /// An addition, a multiplication or a relative base adjustment does not fit in an `i64`.
+    Overflow { position: usize, instruction: i64 },
+    /// A custom instruction returned `Effect::Store` without a destination parameter to store to.
+    MissingDestination { position: usize, instruction: i64 },
+    /// A custom instruction returned `Effect::Fail`, such as a division by zero.
+    InstructionFailed { position: usize, instruction: i64 },
+}
+
+impl IntcodeError {
+    /// Position of the instruction that caused the error.
+    pub fn position(&self) -> usize {
+        match *self {
+            IntcodeError::UnknownOpcode { position, .. }
+            | IntcodeError::InvalidParameterMode { position, .. }
+            | IntcodeError::NegativeAddress { position, .. }
+            | IntcodeError::ImmediateWrite { position, .. }
+            | IntcodeError::JumpOutOfRange { position, .. }
+            | IntcodeError::MemoryLimit { position, .. }
+            | IntcodeError::Overflow { position, .. }
+            | IntcodeError::MissingDestination { position, .. }
+            | IntcodeError::InstructionFailed { position, .. } => position,
+        }
+    }
+
+    /// Raw value of the instruction that caused the error, parameter modes included.
+    pub fn instruction(&self) -> i64 {
+        match *self {
+            IntcodeError::UnknownOpcode { instruction, .. }
+            | IntcodeError::InvalidParameterMode { instruction, .. }
+            | IntcodeError::NegativeAddress { instruction, .. }
+            | IntcodeError::ImmediateWrite { instruction, .. }
+            | IntcodeError::JumpOutOfRange { instruction, .. }
+            | IntcodeError::MemoryLimit { instruction, .. }
+            | IntcodeError::Overflow { instruction, .. }
+            | IntcodeError::MissingDestination { instruction, .. }
+            | IntcodeError::InstructionFailed { instruction, .. } => instruction,
+        }
+    }
+}
+
+impl fmt::Display for IntcodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            IntcodeError::UnknownOpcode { position, instruction } => {
+                write!(f, "Unknown opcode {} at position {}", instruction % 100, position)
+            }
+            IntcodeError::InvalidParameterMode { position, instruction, mode } => {
+                write!(f, "Invalid parameter mode {} in instruction {} at position {}", mode, instruction, position)
+            }
+            IntcodeError::NegativeAddress { position, instruction, address } => {
+                write!(f, "Negative address {} in instruction {} at position {}", address, instruction, position)
+            }
+            IntcodeError::ImmediateWrite { position, instruction } => {
+                write!(f, "Write in immediate mode in instruction {} at position {}", instruction, position)
+            }
+            IntcodeError::JumpOutOfRange { position, instruction, target } => {
+                write!(f, "Jump to {} out of range in instruction {} at position {}", target, instruction, position)
+            }
+            IntcodeError::MemoryLimit { position, instruction, address } => {
+                write!(f, "Address {} exceeds the memory limit in instruction {} at position {}", address, instruction, position)
+            }
+            IntcodeError::Overflow { position, instruction } => {
+                write!(f, "Arithmetic overflow in instruction {} at position {}", instruction, position)
+            }
+            IntcodeError::MissingDestination { position, instruction } => {
+                write!(f, "Store without a destination in instruction {} at position {}", instruction, position)
+            }
+            IntcodeError::InstructionFailed { position, instruction } => {
+                write!(f, "Instruction {} failed at position {}", instruction, position)
+            }
+        }
+    }
+}
+
+impl Error for IntcodeError {}
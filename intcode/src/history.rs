@@ -0,0 +1,150 @@
+use crate::{Input, IntcodeMachine, MachineStatus, Memory, Output, Step};
+
+/// What is needed to take back an executed instruction.
+#[derive(Debug, Clone)]
+pub(crate) struct Undo {
+    step: Step,
+    /// Length of the tape before the instruction, which may have grown it.
+    tape_length: usize,
+    /// Number of values buffered in the output right after the instruction.
+    /// Its output can only be taken back while there are still as many.
+    buffered_output: usize,
+}
+
+impl<I: Input, O: Output, M: Memory> IntcodeMachine<I, O, M> {
+    /// Keeps enough information about the last `limit` executed instructions to undo them with `step_back`.
+    /// Older instructions are forgotten as new ones execute. A limit of 0 turns the history off.
+    pub fn enable_history(&mut self, limit: usize) {
+        self.history_limit = limit;
+        while self.history.len() > limit {
+            self.history.pop_front();
+        }
+    }
+
+    pub fn disable_history(&mut self) {
+        self.enable_history(0);
+    }
+
+    /// Number of instructions that can currently be undone.
+    pub fn history_len(&self) -> usize {
+        self.history.len()
+    }
+
+    pub(crate) fn remember(&mut self, step: Step, tape_length: usize) {
+        if self.history.len() == self.history_limit {
+            self.history.pop_front();
+        }
+        let buffered_output = self.buffered_output;
+        self.history.push_back(Undo { step, tape_length, buffered_output });
+    }
+
+    /// Undoes the last executed instruction, returning what it had done.
+    /// Overwritten cells, the instruction pointer, the relative base, consumed input and emitted output are
+    /// all put back, leaving the machine ready to run again. Returns `None` when the history is empty.
+    pub fn step_back(&mut self) -> Option<Step> {
+        let Undo { step, tape_length, buffered_output } = self.history.pop_back()?;
+
+        for write in step.writes.iter().rev() {
+            self.tape.set(write.address, write.old);
+        }
+        self.tape.truncate(tape_length);
+        self.cache.clear();
+
+        if let Some((old, _)) = step.relative_base {
+            self.relative_base = old;
+        }
+        if let Some(input) = step.input {
+            self.input.push_front(input);
+        }
+        // Output cleared by a later `run` or taken by `try_next_output` is gone, there is nothing to take back.
+        if step.output.is_some() && self.buffered_output == buffered_output {
+            self.output.unwrite();
+            self.buffered_output -= 1;
+        }
+
+        self.position = step.instruction.address;
+        self.status = MachineStatus::Run;
+        self.watch_hits.clear();
+        Some(step)
+    }
+
+    /// Undoes up to `count` instructions, returning how many were undone.
+    pub fn rewind(&mut self, count: usize) -> usize {
+        (0..count).take_while(|_| self.step_back().is_some()).count()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::fixtures::countdown_program;
+    use crate::{IntcodeMachine, MachineStatus};
+
+    #[test]
+    fn test_rewind_to_start() {
+        let mut machine = IntcodeMachine::new(countdown_program()).with_input(3);
+        let start = machine.snapshot();
+        machine.enable_history(1000);
+
+        assert_eq!(machine.run(), vec![3, 2, 1]);
+        assert_eq!(machine.tape().len(), 2000);
+        assert_eq!(machine.relative_base(), 100);
+
+        assert_eq!(machine.rewind(usize::MAX), 13);
+        assert_eq!(machine.snapshot(), start);
+        assert_eq!(machine.step_back(), None);
+
+        assert_eq!(machine.run(), vec![3, 2, 1]);
+    }
+
+    #[test]
+    fn test_step_back_undoes_one_instruction() {
+        let mut machine = IntcodeMachine::new(countdown_program()).with_input(2);
+        machine.enable_history(10);
+
+        for _ in 0..5 {
+            machine.step().unwrap();
+        }
+        assert_eq!(machine.position(), 4);
+        assert_eq!(machine.output(), &[2]);
+        assert_eq!(machine.peek(18), 1);
+
+        let step = machine.step_back().unwrap();
+        assert_eq!(step.instruction.to_string(), "jnz [18], #4");
+        assert_eq!(machine.position(), 10);
+
+        machine.step_back();
+        assert_eq!(machine.peek(18), 2);
+        machine.step_back();
+        assert_eq!(machine.output(), &[] as &[i64]);
+        assert_eq!(machine.status(), MachineStatus::Run);
+    }
+
+    #[test]
+    fn test_step_back_skips_output_already_taken() {
+        let mut machine = IntcodeMachine::new(countdown_program()).with_input(3);
+        machine.enable_history(100);
+        while machine.output().is_empty() {
+            machine.step().unwrap();
+        }
+        assert_eq!(machine.try_next_output(), Ok(Some(2)));
+
+        let step = machine.step_back().unwrap();
+        assert_eq!(step.output, Some(2));
+        assert_eq!(machine.output(), &[3]);
+
+        machine.rewind(usize::MAX);
+        assert_eq!(machine.output(), &[] as &[i64]);
+        assert_eq!(machine.position(), 0);
+    }
+
+    #[test]
+    fn test_history_is_bounded() {
+        let mut machine = IntcodeMachine::new(countdown_program()).with_input(3);
+        machine.enable_history(4);
+        machine.run();
+
+        assert_eq!(machine.history_len(), 4);
+        assert_eq!(machine.rewind(10), 4);
+        assert_eq!(machine.position(), 6);
+    }
+}
@@ -0,0 +1,224 @@
+use std::fmt;
+
+/// The operations understood by the Intcode machine.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub enum Opcode {
+    Add,
+    Mul,
+    St,
+    Ld,
+    Jnz,
+    Jz,
+    Tlt,
+    Teq,
+    Rel,
+    Halt,
+    /// An instruction added by an `InstructionSet`, as the machine decodes it. Never part of `OPCODES`.
+    Custom { code: i64, mnemonic: &'static str, arity: usize, writes: bool },
+}
+
+/// How an instruction parameter is resolved to a value.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub enum ParameterMode {
+    Positional,
+    Immediate,
+    Relative,
+}
+
+/// A decoded instruction parameter: its mode and the raw value stored on the tape.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub struct Operand {
+    pub mode: ParameterMode,
+    pub value: i64,
+}
+
+/// An instruction decoded from the tape without executing it.
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct Instruction {
+    pub address: usize,
+    pub opcode: Opcode,
+    pub operands: Vec<Operand>,
+}
+
+pub const OPCODES: [Opcode; 10] = [
+    Opcode::Add,
+    Opcode::Mul,
+    Opcode::St,
+    Opcode::Ld,
+    Opcode::Jnz,
+    Opcode::Jz,
+    Opcode::Tlt,
+    Opcode::Teq,
+    Opcode::Rel,
+    Opcode::Halt,
+];
+
+impl Opcode {
+    pub fn from_code(code: i64) -> Option<Opcode> {
+        OPCODES.iter().copied().find(|opcode| opcode.code() == code)
+    }
+
+    pub fn from_mnemonic(mnemonic: &str) -> Option<Opcode> {
+        OPCODES.iter().copied().find(|opcode| opcode.mnemonic() == mnemonic)
+    }
+
+    pub fn code(self) -> i64 {
+        match self {
+            Opcode::Add => 1,
+            Opcode::Mul => 2,
+            Opcode::St => 3,
+            Opcode::Ld => 4,
+            Opcode::Jnz => 5,
+            Opcode::Jz => 6,
+            Opcode::Tlt => 7,
+            Opcode::Teq => 8,
+            Opcode::Rel => 9,
+            Opcode::Halt => 99,
+            Opcode::Custom { code, .. } => code,
+        }
+    }
+
+    /// The name of the machine method implementing this opcode, or the mnemonic of a custom instruction.
+    pub fn mnemonic(self) -> &'static str {
+        match self {
+            Opcode::Add => "add",
+            Opcode::Mul => "mul",
+            Opcode::St => "st",
+            Opcode::Ld => "ld",
+            Opcode::Jnz => "jnz",
+            Opcode::Jz => "jz",
+            Opcode::Tlt => "tlt",
+            Opcode::Teq => "teq",
+            Opcode::Rel => "rel",
+            Opcode::Halt => "halt",
+            Opcode::Custom { mnemonic, .. } => mnemonic,
+        }
+    }
+
+    /// Number of parameters following the opcode on the tape.
+    pub fn arity(self) -> usize {
+        match self {
+            Opcode::Add | Opcode::Mul | Opcode::Tlt | Opcode::Teq => 3,
+            Opcode::Jnz | Opcode::Jz => 2,
+            Opcode::St | Opcode::Ld | Opcode::Rel => 1,
+            Opcode::Halt => 0,
+            Opcode::Custom { arity, .. } => arity,
+        }
+    }
+
+    /// Whether the last parameter is an address the instruction writes to.
+    pub fn writes(self) -> bool {
+        match self {
+            Opcode::Custom { writes, .. } => writes,
+            _ => matches!(self, Opcode::Add | Opcode::Mul | Opcode::Tlt | Opcode::Teq | Opcode::St),
+        }
+    }
+
+    pub fn is_jump(self) -> bool {
+        self == Opcode::Jnz || self == Opcode::Jz
+    }
+}
+
+impl ParameterMode {
+    pub fn from_digit(digit: i64) -> Option<ParameterMode> {
+        match digit {
+            0 => Some(ParameterMode::Positional),
+            1 => Some(ParameterMode::Immediate),
+            2 => Some(ParameterMode::Relative),
+            _ => None,
+        }
+    }
+
+    pub fn digit(self) -> i64 {
+        match self {
+            ParameterMode::Positional => 0,
+            ParameterMode::Immediate => 1,
+            ParameterMode::Relative => 2,
+        }
+    }
+}
+
+impl Instruction {
+    /// Decodes the instruction at `address`.
+    /// Returns `None` if the cell does not hold a valid instruction: an unknown opcode or parameter mode,
+    /// a write through an immediate parameter, or parameters running past the end of the tape.
+    pub fn decode(tape: &[i64], address: usize) -> Option<Instruction> {
+        let value = *tape.get(address)?;
+        let opcode = Opcode::from_code(value % 100)?;
+
+        let mut modes = value / 100;
+        let mut operands = Vec::with_capacity(opcode.arity());
+        for offset in 1..=opcode.arity() {
+            let mode = ParameterMode::from_digit(modes % 10)?;
+            let value = *tape.get(address + offset)?;
+            operands.push(Operand { mode, value });
+            modes /= 10;
+        }
+
+        if opcode.writes() && operands.last()?.mode == ParameterMode::Immediate {
+            return None;
+        }
+
+        Some(Instruction { address, opcode, operands })
+    }
+
+    /// Raw value of the first cell of this instruction, parameter modes included.
+    pub fn encode(&self) -> i64 {
+        self.operands.iter().rev()
+            .fold(0, |modes, operand| modes * 10 + operand.mode.digit()) * 100 + self.opcode.code()
+    }
+
+    /// Number of tape cells taken by this instruction.
+    pub fn size(&self) -> usize {
+        self.operands.len() + 1
+    }
+
+    /// Address of the instruction that follows this one on the tape.
+    pub fn next(&self) -> usize {
+        self.address + self.size()
+    }
+
+    /// The address a jump goes to, when it can be known without running the program.
+    pub fn jump_target(&self) -> Option<usize> {
+        if !self.opcode.is_jump() {
+            return None;
+        }
+
+        match self.operands[1] {
+            Operand { mode: ParameterMode::Immediate, value } if value >= 0 => Some(value as usize),
+            _ => None,
+        }
+    }
+
+    /// Whether execution can continue with the next instruction on the tape.
+    /// False for `halt` and for jumps whose condition is an immediate that always takes them.
+    pub fn falls_through(&self) -> bool {
+        match (self.opcode, self.operands.first()) {
+            (Opcode::Halt, _) => false,
+            (Opcode::Jnz, Some(Operand { mode: ParameterMode::Immediate, value })) => *value == 0,
+            (Opcode::Jz, Some(Operand { mode: ParameterMode::Immediate, value })) => *value != 0,
+            _ => true,
+        }
+    }
+}
+
+impl fmt::Display for Operand {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.mode {
+            ParameterMode::Positional => write!(f, "[{}]", self.value),
+            ParameterMode::Immediate => write!(f, "#{}", self.value),
+            ParameterMode::Relative if self.value < 0 => write!(f, "rb-{}", self.value.unsigned_abs()),
+            ParameterMode::Relative => write!(f, "rb+{}", self.value),
+        }
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.opcode.mnemonic())?;
+        for (i, operand) in self.operands.iter().enumerate() {
+            write!(f, "{}{}", if i == 0 { " " } else { ", " }, operand)?;
+        }
+        Ok(())
+    }
+}
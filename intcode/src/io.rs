@@ -0,0 +1,115 @@
+/// Where a machine pulls input from once its queue of values added with `add_input` runs dry.
+pub trait Input {
+    /// Returns the next input value, or `None` to make the machine yield until more input is added.
+    fn read(&mut self) -> Option<i64>;
+}
+
+/// Where a machine pushes the values emitted by `ld` instructions.
+pub trait Output {
+    fn write(&mut self, value: i64);
+
+    /// Called when `run` starts. Buffers drop the output of the previous run, sinks usually ignore it.
+    fn start_run(&mut self) {}
+
+    /// Takes back the last value written, when stepping back through the history.
+    /// Sinks that already handed the value over somewhere else cannot, and ignore it.
+    fn unwrite(&mut self) {}
+}
+
+/// No input source: the machine only reads values queued with `add_input`.
+impl Input for () {
+    fn read(&mut self) -> Option<i64> {
+        None
+    }
+}
+
+/// Buffers the output produced by each run, the default for machines.
+impl Output for Vec<i64> {
+    fn write(&mut self, value: i64) {
+        self.push(value);
+    }
+
+    fn start_run(&mut self) {
+        self.clear();
+    }
+
+    fn unwrite(&mut self) {
+        self.pop();
+    }
+}
+
+/// Input produced on demand by a closure.
+#[derive(Clone)]
+pub struct InputFn<F: FnMut() -> Option<i64>>(pub F);
+
+/// Output handed straight to a closure.
+#[derive(Clone)]
+pub struct OutputFn<F: FnMut(i64)>(pub F);
+
+impl<F: FnMut() -> Option<i64>> Input for InputFn<F> {
+    fn read(&mut self) -> Option<i64> {
+        (self.0)()
+    }
+}
+
+impl<F: FnMut(i64)> Output for OutputFn<F> {
+    fn write(&mut self, value: i64) {
+        (self.0)(value)
+    }
+}
+
+/// Input taken from an iterator, for instance the lines of a file parsed into numbers.
+#[derive(Clone)]
+pub struct InputIter<T: Iterator<Item = i64>>(pub T);
+
+impl<T: Iterator<Item = i64>> Input for InputIter<T> {
+    fn read(&mut self) -> Option<i64> {
+        self.0.next()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{InputFn, InputIter, OutputFn};
+    use crate::{IntcodeMachine, MachineStatus};
+    use std::cell::RefCell;
+
+    fn doubler_program() -> Vec<i64> {
+        // Reads a value, outputs it doubled and starts over.
+        vec![3, 9, 1002, 9, 2, 9, 4, 9, 1105, 1, 0]
+    }
+
+    #[test]
+    fn test_input_is_pulled_on_demand() {
+        let mut next = 0;
+        let mut machine = IntcodeMachine::new(doubler_program())
+            .with_source(InputFn(|| {
+                next += 1;
+                if next <= 3 { Some(next) } else { None }
+            }));
+
+        assert_eq!(machine.run(), vec![2, 4, 6]);
+        assert!(machine.yielded());
+    }
+
+    #[test]
+    fn test_queued_input_comes_before_the_source() {
+        let mut machine = IntcodeMachine::new(doubler_program())
+            .with_input(10)
+            .with_source(InputIter(vec![1, 2].into_iter()));
+
+        assert_eq!(machine.run(), vec![20, 2, 4]);
+    }
+
+    #[test]
+    fn test_output_is_pushed_to_the_sink() {
+        let received = RefCell::new(vec![]);
+        let mut machine = IntcodeMachine::new(doubler_program())
+            .with_source(InputIter(1..=4))
+            .with_sink(OutputFn(|value| received.borrow_mut().push(value)));
+
+        assert_eq!(machine.resume(), MachineStatus::Yield);
+        drop(machine);
+        assert_eq!(received.into_inner(), vec![2, 4, 6, 8]);
+    }
+}
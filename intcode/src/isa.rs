@@ -0,0 +1,522 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt;
+use std::sync::Arc;
+
+use crate::disasm::find_code;
+use crate::instruction::{Opcode, ParameterMode, OPCODES};
+use crate::{Input, IntcodeError, IntcodeMachine, MachineStatus, Memory, Output};
+
+/// What a custom instruction does once its parameters are read.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Effect {
+    /// Continues with the next instruction.
+    Next,
+    /// Writes the value to the destination parameter, then continues with the next instruction.
+    Store(i64),
+    Jump(i64),
+    Halt,
+    /// Stops the machine with `IntcodeError::InstructionFailed`, for parameters the instruction cannot handle.
+    Fail,
+}
+
+/// An instruction added to an instruction set, on top of or in place of the built-in ones.
+#[derive(Clone, Copy)]
+pub struct CustomInstruction {
+    pub mnemonic: &'static str,
+    /// Number of parameters following the opcode on the tape.
+    pub arity: usize,
+    /// Whether the last parameter is an address the instruction writes to.
+    /// It is resolved like the destinations of built-in instructions, rejecting immediate mode.
+    pub writes: bool,
+    /// The modes each parameter accepts, by position, on top of the modes the instruction set allows.
+    /// Parameters past the end of the list accept every mode, so `&[]` leaves them all unrestricted.
+    pub modes: &'static [&'static [ParameterMode]],
+    /// Computes the effect of the instruction from the values of its parameters, destination excluded.
+    /// Only instructions that write can return `Effect::Store`, the others fail with `IntcodeError::MissingDestination`.
+    pub execute: fn(&[i64]) -> Effect,
+}
+
+impl fmt::Debug for CustomInstruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("CustomInstruction")
+            .field("mnemonic", &self.mnemonic)
+            .field("arity", &self.arity)
+            .field("writes", &self.writes)
+            .field("modes", &self.modes)
+            .finish()
+    }
+}
+
+/// How an instruction set implements an opcode.
+#[derive(Debug, Clone, Copy)]
+pub enum Definition {
+    /// One of the built-in opcodes. An `Opcode::Custom` here is treated as unknown.
+    Builtin(Opcode),
+    Custom(CustomInstruction),
+}
+
+impl Definition {
+    pub fn arity(&self) -> usize {
+        match self {
+            Definition::Builtin(opcode) => opcode.arity(),
+            Definition::Custom(instruction) => instruction.arity,
+        }
+    }
+
+    /// The opcode an instruction with this definition decodes to, `code` being the two lowest digits of the instruction.
+    pub fn opcode(&self, code: i64) -> Opcode {
+        match *self {
+            Definition::Builtin(opcode) => opcode,
+            Definition::Custom(CustomInstruction { mnemonic, arity, writes, .. }) => {
+                Opcode::Custom { code, mnemonic, arity, writes }
+            }
+        }
+    }
+}
+
+/// The opcodes a machine understands and the parameter modes they accept.
+/// Without one the machine runs the full Intcode instruction set.
+///
+/// The machine follows its instruction set whether it runs, steps, traces or records history, the decoded engine
+/// handing every instruction over to the interpreter. The disassembler, the assembler, the compiler and the analyses
+/// built on them work on the tape alone and keep to the built-in opcodes.
+pub trait InstructionSet: Send + Sync {
+    /// How to execute opcode `code`, or `None` if the set does not have it.
+    fn definition(&self, code: i64) -> Option<Definition>;
+
+    /// Whether parameter `parameter` of opcode `code`, counted from 0, can use `mode`. Every mode is allowed by default.
+    fn allows_mode(&self, _code: i64, _parameter: usize, _mode: ParameterMode) -> bool {
+        true
+    }
+}
+
+/// The successive specifications of Intcode, named after the puzzle that introduced them.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub enum Revision {
+    /// Only `add`, `mul` and `halt`, with positional parameters.
+    Day02,
+    /// Adds input, output, jumps and comparisons, as well as immediate parameters.
+    Day05,
+    /// Adds the relative base and its parameter mode. The complete instruction set.
+    Day09,
+}
+
+impl Revision {
+    /// The first revision with `opcode`. Custom opcodes are in none of them, and count as the latest.
+    pub fn of_opcode(opcode: Opcode) -> Revision {
+        match opcode {
+            Opcode::Add | Opcode::Mul | Opcode::Halt => Revision::Day02,
+            Opcode::Rel | Opcode::Custom { .. } => Revision::Day09,
+            _ => Revision::Day05,
+        }
+    }
+
+    /// The first revision with parameters in `mode`.
+    pub fn of_mode(mode: ParameterMode) -> Revision {
+        match mode {
+            ParameterMode::Positional => Revision::Day02,
+            ParameterMode::Immediate => Revision::Day05,
+            ParameterMode::Relative => Revision::Day09,
+        }
+    }
+
+    /// The earliest revision able to run the code the disassembler finds on `tape`.
+    /// Code it cannot find, such as code jumped to through a parameter, is not checked.
+    pub fn required(tape: &[i64]) -> Revision {
+        find_code(tape).values()
+            .flat_map(|instruction| {
+                let modes = instruction.operands.iter().map(|operand| Revision::of_mode(operand.mode));
+                modes.chain(Some(Revision::of_opcode(instruction.opcode)))
+            })
+            .max()
+            .unwrap_or(Revision::Day02)
+    }
+}
+
+impl InstructionSet for Revision {
+    fn definition(&self, code: i64) -> Option<Definition> {
+        Opcode::from_code(code)
+            .filter(|&opcode| Revision::of_opcode(opcode) <= *self)
+            .map(Definition::Builtin)
+    }
+
+    fn allows_mode(&self, _code: i64, _parameter: usize, mode: ParameterMode) -> bool {
+        Revision::of_mode(mode) <= *self
+    }
+}
+
+/// Error returned when registering an opcode that cannot be the two lowest digits of an instruction.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct InvalidOpcode(pub i64);
+
+impl fmt::Display for InvalidOpcode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Opcode {} is outside 0..100", self.0)
+    }
+}
+
+impl Error for InvalidOpcode {}
+
+/// An instruction set built by adding opcodes to and removing them from one of the revisions.
+#[derive(Debug, Clone)]
+pub struct OpcodeTable {
+    definitions: BTreeMap<i64, Definition>,
+    modes: Vec<ParameterMode>,
+}
+
+impl OpcodeTable {
+    /// The complete instruction set.
+    pub fn new() -> OpcodeTable {
+        OpcodeTable::from(Revision::Day09)
+    }
+
+    /// Adds `instruction` as opcode `code`, replacing any instruction it had.
+    /// Fails if `code` is outside `0..100`, as the machine could never decode it.
+    pub fn register(&mut self, code: i64, instruction: CustomInstruction) -> Result<(), InvalidOpcode> {
+        if !(0..100).contains(&code) {
+            return Err(InvalidOpcode(code));
+        }
+        self.definitions.insert(code, Definition::Custom(instruction));
+        Ok(())
+    }
+
+    pub fn with_instruction(mut self, code: i64, instruction: CustomInstruction) -> Result<Self, InvalidOpcode> {
+        self.register(code, instruction)?;
+        Ok(self)
+    }
+
+    /// Removes opcode `code`, which then fails as an unknown opcode.
+    pub fn disable(&mut self, code: i64) {
+        self.definitions.remove(&code);
+    }
+
+    pub fn without(mut self, code: i64) -> Self {
+        self.disable(code);
+        self
+    }
+
+    /// Restricts the parameter modes of every instruction to `modes`.
+    pub fn set_modes(&mut self, modes: &[ParameterMode]) {
+        self.modes = modes.to_vec();
+    }
+
+    pub fn with_modes(mut self, modes: &[ParameterMode]) -> Self {
+        self.set_modes(modes);
+        self
+    }
+}
+
+impl Default for OpcodeTable {
+    fn default() -> Self {
+        OpcodeTable::new()
+    }
+}
+
+impl From<Revision> for OpcodeTable {
+    fn from(revision: Revision) -> Self {
+        OpcodeTable {
+            definitions: OPCODES.iter()
+                .filter(|&&opcode| Revision::of_opcode(opcode) <= revision)
+                .map(|&opcode| (opcode.code(), Definition::Builtin(opcode)))
+                .collect(),
+            modes: [ParameterMode::Positional, ParameterMode::Immediate, ParameterMode::Relative].iter()
+                .copied()
+                .filter(|&mode| Revision::of_mode(mode) <= revision)
+                .collect(),
+        }
+    }
+}
+
+impl InstructionSet for OpcodeTable {
+    fn definition(&self, code: i64) -> Option<Definition> {
+        self.definitions.get(&code).copied()
+    }
+
+    fn allows_mode(&self, code: i64, parameter: usize, mode: ParameterMode) -> bool {
+        let custom = match self.definitions.get(&code) {
+            Some(Definition::Custom(instruction)) => instruction.modes.get(parameter).is_none_or(|modes| modes.contains(&mode)),
+            _ => true,
+        };
+        custom && self.modes.contains(&mode)
+    }
+}
+
+impl<I: Input, O: Output, M: Memory> IntcodeMachine<I, O, M> {
+    /// Runs programs with `set` instead of the built-in instructions.
+    /// The decoded engine only knows the built-in instructions, so the machine interprets every instruction from then on.
+    pub fn set_instruction_set<S: InstructionSet + 'static>(&mut self, set: S) {
+        self.instruction_set = Some(Arc::new(set));
+        self.cache.clear();
+    }
+
+    pub fn with_instruction_set<S: InstructionSet + 'static>(mut self, set: S) -> Self {
+        self.set_instruction_set(set);
+        self
+    }
+
+    /// Goes back to the built-in instructions.
+    pub fn clear_instruction_set(&mut self) {
+        self.instruction_set = None;
+    }
+
+    /// Executes the instruction under the instruction pointer as defined by `set`, returning the opcode it ran as.
+    pub(crate) fn execute_in(&mut self, set: &dyn InstructionSet, code: i64) -> Result<Opcode, IntcodeError> {
+        let definition = set.definition(code).ok_or(IntcodeError::UnknownOpcode {
+            position: self.position,
+            instruction: self.instruction(),
+        })?;
+
+        let mut modes = self.instruction() / 100;
+        for parameter in 0..definition.arity() {
+            let mode = self.parse_mode(modes % 10)?;
+            if !set.allows_mode(code, parameter, mode) {
+                return Err(IntcodeError::InvalidParameterMode {
+                    position: self.position,
+                    instruction: self.instruction(),
+                    mode: modes % 10,
+                });
+            }
+            modes /= 10;
+        }
+
+        match definition {
+            Definition::Builtin(opcode) => self.execute_opcode(opcode)?,
+            Definition::Custom(instruction) => self.execute_custom(&instruction)?,
+        }
+        Ok(definition.opcode(code))
+    }
+
+    fn execute_opcode(&mut self, opcode: Opcode) -> Result<(), IntcodeError> {
+        match opcode {
+            Opcode::Add => self.add(),
+            Opcode::Mul => self.mul(),
+            Opcode::St => self.st(),
+            Opcode::Ld => self.ld(),
+            Opcode::Jnz => self.jnz(),
+            Opcode::Jz => self.jz(),
+            Opcode::Tlt => self.tlt(),
+            Opcode::Teq => self.teq(),
+            Opcode::Rel => self.rel(),
+            Opcode::Halt => self.halt(),
+            Opcode::Custom { .. } => Err(IntcodeError::UnknownOpcode {
+                position: self.position,
+                instruction: self.instruction(),
+            }),
+        }
+    }
+
+    fn execute_custom(&mut self, instruction: &CustomInstruction) -> Result<(), IntcodeError> {
+        let reads = if instruction.writes { instruction.arity - 1 } else { instruction.arity };
+        let mut modes = self.instruction() / 100;
+        let mut args = Vec::with_capacity(reads);
+        for offset in 1..=reads {
+            let mode = self.parse_mode(modes % 10)?;
+            args.push(self.fetch_arg(mode, offset)?);
+            modes /= 10;
+        }
+        let dest = if instruction.writes {
+            let mode = self.parse_mode(modes % 10)?;
+            Some(self.fetch_dest(mode, instruction.arity)?)
+        } else {
+            None
+        };
+
+        match (instruction.execute)(&args) {
+            Effect::Next => self.position += instruction.arity + 1,
+            Effect::Store(value) => {
+                let dest = dest.ok_or(IntcodeError::MissingDestination {
+                    position: self.position,
+                    instruction: self.instruction(),
+                })?;
+                self.store(dest, value)?;
+                self.position += instruction.arity + 1;
+            }
+            Effect::Jump(target) => self.jump(target)?,
+            Effect::Halt => self.status = MachineStatus::Halt,
+            Effect::Fail => {
+                return Err(IntcodeError::InstructionFailed {
+                    position: self.position,
+                    instruction: self.instruction(),
+                })
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CustomInstruction, Effect, InvalidOpcode, OpcodeTable, Revision};
+    use crate::{read_binary_trace, read_binary_trace_in, Engine, IntcodeError, IntcodeMachine, MachineStatus,
+                MemoryWrite, ParameterMode, TraceFormat, TraceWriter};
+
+    const DIV: CustomInstruction = CustomInstruction {
+        mnemonic: "div",
+        arity: 3,
+        writes: true,
+        modes: &[],
+        execute: |args| args[0].checked_div(args[1]).map_or(Effect::Fail, Effect::Store),
+    };
+
+    const MOD: CustomInstruction = CustomInstruction {
+        mnemonic: "mod",
+        arity: 3,
+        writes: true,
+        modes: &[],
+        execute: |args| args[0].checked_rem(args[1]).map_or(Effect::Fail, Effect::Store),
+    };
+
+    #[test]
+    fn test_custom_opcodes() {
+        // Reads a number, outputs its quotient and remainder by 7.
+        let tape = vec![3, 100, 1010, 100, 7, 101, 1011, 100, 7, 102, 4, 101, 4, 102, 99];
+        let table = OpcodeTable::new().with_instruction(10, DIV).unwrap().with_instruction(11, MOD).unwrap();
+        let mut machine = IntcodeMachine::new(tape).with_instruction_set(table).with_engine(Engine::Decoded);
+        machine.add_input(45);
+
+        assert_eq!(machine.run(), vec![6, 3]);
+        assert!(machine.halted());
+    }
+
+    #[test]
+    fn test_step_trace_and_history_with_custom_opcodes() {
+        let tape = vec![3, 100, 1010, 100, 7, 101, 1011, 100, 7, 102, 4, 101, 4, 102, 99];
+        let table = || OpcodeTable::new().with_instruction(10, DIV).unwrap().with_instruction(11, MOD).unwrap();
+        let mut machine = IntcodeMachine::new(tape).with_instruction_set(table());
+        machine.add_input(45);
+        machine.enable_history(10);
+        machine.start_tracing();
+
+        machine.step().unwrap();
+        let step = machine.step().unwrap();
+        assert_eq!(step.instruction.to_string(), "div [100], #7, [101]");
+        assert_eq!(step.writes, vec![MemoryWrite { address: 101, old: 0, new: 6 }]);
+        assert_eq!(machine.run(), vec![6, 3]);
+
+        let trace = machine.take_trace();
+        assert_eq!(trace.len(), 6);
+        let mut writer = TraceWriter::new(vec![], TraceFormat::Binary);
+        writer.write_all(&trace).unwrap();
+        let bytes = writer.into_inner();
+        assert_eq!(read_binary_trace_in(&mut bytes.as_slice(), &table()).unwrap(), trace);
+        assert!(read_binary_trace(&mut bytes.as_slice()).is_err());
+
+        assert_eq!(machine.rewind(4), 4);
+        assert_eq!(machine.position, 6);
+        assert_eq!(machine.peek(102), 0);
+        assert_eq!(machine.run(), vec![6, 3]);
+    }
+
+    #[test]
+    fn test_step_follows_revision() {
+        let mut machine = IntcodeMachine::new(vec![3, 0, 4, 0, 99]).with_instruction_set(Revision::Day02);
+        assert_eq!(machine.step(), Err(IntcodeError::UnknownOpcode { position: 0, instruction: 3 }));
+
+        let mut machine = IntcodeMachine::new(vec![1101, 2, 3, 0, 99]).with_instruction_set(Revision::Day02);
+        machine.start_tracing();
+        assert_eq!(machine.step(), Err(IntcodeError::InvalidParameterMode { position: 0, instruction: 1101, mode: 1 }));
+        assert!(machine.take_trace().is_empty());
+    }
+
+    #[test]
+    fn test_custom_instruction_errors() {
+        const BAD: CustomInstruction = CustomInstruction {
+            mnemonic: "bad",
+            arity: 0,
+            writes: false,
+            modes: &[],
+            execute: |_| Effect::Store(1),
+        };
+        let table = OpcodeTable::new().with_instruction(10, DIV).unwrap().with_instruction(12, BAD).unwrap();
+
+        let mut machine = IntcodeMachine::new(vec![12, 99]).with_instruction_set(table.clone());
+        assert_eq!(machine.try_run(), Err(IntcodeError::MissingDestination { position: 0, instruction: 12 }));
+
+        let mut machine = IntcodeMachine::new(vec![1110, 1, 0, 0, 99]).with_instruction_set(table);
+        assert_eq!(machine.try_run(), Err(IntcodeError::InstructionFailed { position: 0, instruction: 1110 }));
+        assert_eq!(machine.peek(0), 1110);
+    }
+
+    #[test]
+    fn test_custom_jump_and_halt() {
+        const JMP: CustomInstruction = CustomInstruction {
+            mnemonic: "jmp",
+            arity: 1,
+            writes: false,
+            modes: &[&[ParameterMode::Immediate]],
+            execute: |args| Effect::Jump(args[0]),
+        };
+        const STOP: CustomInstruction = CustomInstruction {
+            mnemonic: "stop",
+            arity: 0,
+            writes: false,
+            modes: &[],
+            execute: |_| Effect::Halt,
+        };
+        let tape = vec![112, 4, 104, 1, 104, 2, 13];
+        let table = OpcodeTable::new().with_instruction(12, JMP).unwrap().with_instruction(13, STOP).unwrap().without(99);
+        let mut machine = IntcodeMachine::new(tape).with_instruction_set(table.clone());
+
+        assert_eq!(machine.run(), vec![2]);
+        assert_eq!(machine.status(), MachineStatus::Halt);
+
+        let mut machine = IntcodeMachine::new(vec![12, 4, 99]).with_instruction_set(table.clone());
+        assert_eq!(machine.step(), Err(IntcodeError::InvalidParameterMode { position: 0, instruction: 12, mode: 0 }));
+        assert_eq!(machine.try_run(), Err(IntcodeError::InvalidParameterMode { position: 0, instruction: 12, mode: 0 }));
+    }
+
+    #[test]
+    fn test_register_rejects_undecodable_opcodes() {
+        let mut table = OpcodeTable::new();
+
+        assert_eq!(table.register(100, DIV), Err(InvalidOpcode(100)));
+        assert_eq!(table.register(-1, DIV), Err(InvalidOpcode(-1)));
+        assert_eq!(table.register(10, DIV), Ok(()));
+        assert!(OpcodeTable::new().with_instruction(1010, MOD).is_err());
+    }
+
+    #[test]
+    fn test_disabled_opcode() {
+        let table = OpcodeTable::new().without(2);
+        let mut machine = IntcodeMachine::new(vec![1, 0, 0, 0, 2, 0, 0, 0, 99]).with_instruction_set(table);
+
+        assert_eq!(machine.try_run(), Err(IntcodeError::UnknownOpcode { position: 4, instruction: 2 }));
+        assert_eq!(machine.peek(0), 2);
+    }
+
+    #[test]
+    fn test_revisions() {
+        let day02 = vec![1, 9, 10, 3, 2, 3, 11, 0, 99, 30, 40, 50];
+        let mut machine = IntcodeMachine::new(day02.clone()).with_instruction_set(Revision::Day02);
+        machine.run();
+        assert_eq!(machine.peek(0), 3500);
+
+        let day05 = vec![3, 0, 4, 0, 99];
+        let mut machine = IntcodeMachine::new(day05.clone()).with_instruction_set(Revision::Day02);
+        assert_eq!(machine.try_run(), Err(IntcodeError::UnknownOpcode { position: 0, instruction: 3 }));
+
+        let immediate = vec![1101, 2, 3, 0, 99];
+        let mut machine = IntcodeMachine::new(immediate.clone()).with_instruction_set(Revision::Day02);
+        assert_eq!(machine.try_run(), Err(IntcodeError::InvalidParameterMode { position: 0, instruction: 1101, mode: 1 }));
+
+        let relative = vec![109, 1, 204, -1, 99];
+        let mut machine = IntcodeMachine::new(relative.clone()).with_instruction_set(Revision::Day05);
+        assert_eq!(machine.try_run(), Err(IntcodeError::UnknownOpcode { position: 0, instruction: 109 }));
+        let mut machine = IntcodeMachine::new(relative.clone()).with_instruction_set(Revision::Day09);
+        assert_eq!(machine.run(), vec![109]);
+
+        assert_eq!(Revision::required(&day02), Revision::Day02);
+        assert_eq!(Revision::required(&day05), Revision::Day05);
+        assert_eq!(Revision::required(&immediate), Revision::Day05);
+        assert_eq!(Revision::required(&relative), Revision::Day09);
+    }
+
+    #[test]
+    fn test_mode_rules() {
+        let table = OpcodeTable::new().with_modes(&[ParameterMode::Positional, ParameterMode::Immediate]);
+        let mut machine = IntcodeMachine::new(vec![1, 0, 0, 0, 22201, 0, 0, 0, 99]).with_instruction_set(table);
+
+        assert_eq!(machine.try_run(), Err(IntcodeError::InvalidParameterMode { position: 4, instruction: 22201, mode: 2 }));
+    }
+}
@@ -1,59 +1,205 @@
-use std::collections::vec_deque::VecDeque;
+mod analysis;
+mod aot;
+mod ascii;
+mod asm;
+mod decompile;
+mod disasm;
+mod engine;
+mod error;
+mod history;
+mod instruction;
+mod io;
+mod isa;
+mod limit;
+mod memory;
+mod network;
+mod outputs;
+mod packet;
+mod profile;
+mod save;
+mod snapshot;
+mod solver;
+mod step;
+mod symbolic;
+mod trace;
+mod watch;
+
+pub use analysis::{analyze, Block, Call, CodeWrite, ControlFlowGraph, Edge, EdgeKind};
+pub use aot::{compile, CompiledCode, CompiledMachine};
+pub use ascii::{AsciiEvent, AsciiMachine};
+pub use asm::{assemble, AssembleError};
+pub use decompile::decompile;
+pub use disasm::{disassemble, Disassembly, Entry, Item};
+pub use engine::Engine;
+pub use error::IntcodeError;
+pub use instruction::{Instruction, Opcode, Operand, ParameterMode};
+pub use io::{Input, InputFn, InputIter, Output, OutputFn};
+pub use isa::{CustomInstruction, Definition, Effect, InstructionSet, InvalidOpcode, OpcodeTable, Revision};
+pub use memory::{cell_hash, DenseMemory, Memory, PagedMemory, Segment};
+pub use network::{ring, spawn_network, MachineThread, NetworkError, Scheduler};
+pub use outputs::{Outputs, Tuples};
+pub use packet::{Nat, Packet, PacketNetwork, Route, Router};
+pub use profile::Profile;
+pub use save::{LoadError, LOAD_LIMIT, SNAPSHOT_VERSION};
+pub use snapshot::Snapshot;
+pub use solver::{SolveError, Solver};
+pub use step::{MemoryRead, MemoryWrite, Step};
+pub use symbolic::{Expr, Linear, SymbolicError, SymbolicMachine};
+pub use trace::{first_divergence, read_binary_trace, read_binary_trace_in, TraceFormat, TraceSink, TraceWriter};
+pub use watch::{Access, WatchHit};
+
+// Lets code generated by `compile` refer to this crate by name in tests.
+#[cfg(test)]
+extern crate self as intcode;
 
-pub struct IntcodeMachine {
-    tape: Vec<i64>,
+use std::collections::vec_deque::VecDeque;
+use std::collections::BTreeSet;
+use std::num::ParseIntError;
+use std::sync::Arc;
+
+use engine::DecodeCache;
+use history::Undo;
+use limit::LoopCheck;
+use trace::Tracer;
+
+/// An Intcode computer reading from `I` once its input queue is empty, writing to `O` and storing its tape in `M`.
+/// By default there is no input source, output is buffered until the next call to `run` and the tape is dense.
+#[derive(Clone)]
+pub struct IntcodeMachine<I = (), O = Vec<i64>, M = DenseMemory> {
+    tape: M,
     position: usize,
     relative_base: isize,
     input: VecDeque<i64>,
-    output: Vec<i64>,
+    source: I,
+    output: O,
     status: MachineStatus,
+    breakpoints: BTreeSet<usize>,
+    watchpoints: BTreeSet<usize>,
+    watch_hits: Vec<WatchHit>,
+    /// Record of the instruction being executed by `step` or while tracing.
+    current: Option<Step>,
+    tracer: Option<Tracer>,
+    trace: Vec<Step>,
+    /// Undo records of the last executed instructions, at most `history_limit` of them.
+    history: VecDeque<Undo>,
+    history_limit: usize,
+    /// Number of values written to the output that are still buffered there, which `step_back` can take back.
+    buffered_output: usize,
+    /// Most instructions the machine may execute once the limit is set.
+    instruction_limit: Option<u64>,
+    /// Instructions executed since the instruction limit was set.
+    budget_used: u64,
+    detect_loops: bool,
+    /// `Memory::state_hash` of the tape, kept up to date by every write while detecting loops.
+    tape_hash: u64,
+    /// A state seen since the last input or output, to compare later states with when detecting loops.
+    loop_check: LoopCheck,
+    profiling: bool,
+    profile: Profile,
+    engine: Engine,
+    cache: DecodeCache,
+    /// Opcodes understood in place of the built-in ones, see `InstructionSet`.
+    instruction_set: Option<Arc<dyn InstructionSet>>,
 }
 
-#[derive(Clone, Copy, Eq, PartialEq)]
-enum ParameterMode {
-    Positional,
-    Immediate,
-    Relative,
+/// Parses a comma separated Intcode program, as found in the puzzle inputs.
+pub fn parse_tape(text: &str) -> Result<Vec<i64>, ParseIntError> {
+    text.trim()
+        .split(',')
+        .map(|num| num.trim().parse::<i64>())
+        .collect()
 }
 
+/// Why the machine stopped running.
 #[derive(Debug, Clone, Copy, Eq, PartialEq)]
-enum MachineStatus {
+pub enum MachineStatus {
     Run,
+    /// Waiting for input on a `st` instruction.
     Yield,
     Halt,
+    /// About to execute an instruction with a breakpoint on it.
+    Breakpoint,
+    /// Just executed an instruction that accessed a watched cell.
+    Watchpoint,
+    /// Executed as many instructions as allowed by the instruction limit.
+    OutOfBudget,
+    /// About to execute an instruction in a state already seen without any input or output in between,
+    /// so the program would run forever.
+    Loop,
 }
 
 impl IntcodeMachine {
     pub fn new(tape: Vec<i64>) -> IntcodeMachine {
+        IntcodeMachine::from_memory(DenseMemory::new(tape))
+    }
+}
+
+impl<M: Memory> IntcodeMachine<(), Vec<i64>, M> {
+    /// Builds a machine running the program already loaded in `memory`, for instance a `PagedMemory`.
+    pub fn from_memory(memory: M) -> Self {
         IntcodeMachine {
-            tape,
+            tape: memory,
             position: 0,
             input: VecDeque::new(),
+            source: (),
             output: vec![],
             status: MachineStatus::Run,
             relative_base: 0,
+            breakpoints: BTreeSet::new(),
+            watchpoints: BTreeSet::new(),
+            watch_hits: vec![],
+            current: None,
+            tracer: None,
+            trace: vec![],
+            history: VecDeque::new(),
+            history_limit: 0,
+            buffered_output: 0,
+            instruction_limit: None,
+            budget_used: 0,
+            detect_loops: false,
+            tape_hash: 0,
+            loop_check: LoopCheck::default(),
+            profiling: false,
+            profile: Profile::default(),
+            engine: Engine::Interpreter,
+            cache: DecodeCache::default(),
+            instruction_set: None,
         }
     }
+}
+
+impl<I: Input, O: Output, M: Memory> IntcodeMachine<I, O, M> {
+    pub fn with_zeroth(mut self, value: i64) -> Self {
+        self.tape.set(0, value);
+        self.cache.clear();
+        self
+    }
+
+    pub fn with_init(mut self, noun: i64, verb: i64) -> Self {
+        self.tape.set(1, noun);
+        self.tape.set(2, verb);
+        self.cache.clear();
+        self
+    }
 
-    pub fn with_zeroth(mut self, value: i64) -> IntcodeMachine {
-        self.tape[0] = value;
-        return self;
+    /// Makes the program fail with a `MemoryLimit` error instead of allocating more than `cells` cells for its tape.
+    pub fn with_memory_limit(mut self, cells: usize) -> Self {
+        self.set_memory_limit(Some(cells));
+        self
     }
 
-    pub fn with_init(mut self, noun: i64, verb: i64) -> IntcodeMachine {
-        self.tape[1] = noun;
-        self.tape[2] = verb;
-        return self;
+    pub fn set_memory_limit(&mut self, cells: Option<usize>) {
+        self.tape.set_limit(cells);
     }
 
     pub fn with_input(mut self, input: i64) -> Self {
         self.add_input(input);
-        return self;
+        self
     }
 
     pub fn with_inputs(mut self, input: &VecDeque<i64>) -> Self {
         self.add_inputs(input);
-        return self;
+        self
     }
 
     pub fn add_inputs(&mut self, input: &VecDeque<i64>) {
@@ -62,185 +208,349 @@ impl IntcodeMachine {
 
     pub fn add_input(&mut self, input: i64) {
         if self.status != MachineStatus::Halt {
-            self.status = MachineStatus::Run;
+            if self.status == MachineStatus::Yield {
+                self.status = MachineStatus::Run;
+            }
             self.input.push_back(input);
         }
     }
 
-    fn parse_mode(&self, i: i64) -> ParameterMode {
-        match i {
-            1 => ParameterMode::Immediate,
-            2 => ParameterMode::Relative,
-            0 | _ => ParameterMode::Positional,
+    /// Pulls input from `source` whenever the queue filled by `add_input` is empty.
+    pub fn with_source<S: Input>(self, source: S) -> IntcodeMachine<S, O, M> {
+        IntcodeMachine {
+            tape: self.tape,
+            position: self.position,
+            relative_base: self.relative_base,
+            input: self.input,
+            source,
+            output: self.output,
+            status: self.status,
+            breakpoints: self.breakpoints,
+            watchpoints: self.watchpoints,
+            watch_hits: self.watch_hits,
+            current: self.current,
+            tracer: self.tracer,
+            trace: self.trace,
+            history: self.history,
+            history_limit: self.history_limit,
+            buffered_output: self.buffered_output,
+            instruction_limit: self.instruction_limit,
+            budget_used: self.budget_used,
+            detect_loops: self.detect_loops,
+            tape_hash: self.tape_hash,
+            loop_check: self.loop_check,
+            profiling: self.profiling,
+            profile: self.profile,
+            engine: self.engine,
+            cache: self.cache,
+            instruction_set: self.instruction_set,
+        }
+    }
+
+    /// Pushes output to `sink` instead of buffering it.
+    pub fn with_sink<S: Output>(self, sink: S) -> IntcodeMachine<I, S, M> {
+        IntcodeMachine {
+            tape: self.tape,
+            position: self.position,
+            relative_base: self.relative_base,
+            input: self.input,
+            source: self.source,
+            output: sink,
+            status: self.status,
+            breakpoints: self.breakpoints,
+            watchpoints: self.watchpoints,
+            watch_hits: self.watch_hits,
+            current: self.current,
+            tracer: self.tracer,
+            trace: self.trace,
+            history: self.history,
+            history_limit: self.history_limit,
+            buffered_output: 0,
+            instruction_limit: self.instruction_limit,
+            budget_used: self.budget_used,
+            detect_loops: self.detect_loops,
+            tape_hash: self.tape_hash,
+            loop_check: self.loop_check,
+            profiling: self.profiling,
+            profile: self.profile,
+            engine: self.engine,
+            cache: self.cache,
+            instruction_set: self.instruction_set,
         }
     }
 
-    fn fetch1mode(&mut self) -> ParameterMode {
-        let parameter_mode = self.tape[self.position] / 100;
+    /// The raw value of the instruction under the instruction pointer, parameter modes included.
+    /// Running off the end of the tape reads zeroes, which decode as an unknown opcode.
+    fn instruction(&self) -> i64 {
+        self.tape.get(self.position)
+    }
+
+    fn parse_mode(&self, i: i64) -> Result<ParameterMode, IntcodeError> {
+        ParameterMode::from_digit(i).ok_or(IntcodeError::InvalidParameterMode {
+            position: self.position,
+            instruction: self.instruction(),
+            mode: i,
+        })
+    }
+
+    fn fetch1mode(&self) -> Result<ParameterMode, IntcodeError> {
+        let parameter_mode = self.instruction() / 100;
         self.parse_mode(parameter_mode % 10)
     }
 
-    fn fetch2modes(&mut self) -> (ParameterMode, ParameterMode) {
-        let mode1 = self.fetch1mode();
-        let mode2 = self.tape[self.position] / 1000;
+    fn fetch2modes(&self) -> Result<(ParameterMode, ParameterMode), IntcodeError> {
+        let mode1 = self.fetch1mode()?;
+        let mode2 = self.instruction() / 1000;
 
-        (self.parse_mode(mode2 % 10), mode1)
+        Ok((self.parse_mode(mode2 % 10)?, mode1))
     }
 
-    fn fetch3modes(&mut self) -> (ParameterMode, ParameterMode, ParameterMode) {
-        let (mode2, mode1) = self.fetch2modes();
-        let mode3 = self.tape[self.position] / 10000;
+    fn fetch3modes(&self) -> Result<(ParameterMode, ParameterMode, ParameterMode), IntcodeError> {
+        let (mode2, mode1) = self.fetch2modes()?;
+        let mode3 = self.instruction() / 10000;
 
-        (self.parse_mode(mode3 % 10), mode2, mode1)
+        Ok((self.parse_mode(mode3 % 10)?, mode2, mode1))
     }
 
-    fn fetch_arg(&mut self, mode: ParameterMode) -> i64 {
-        self.position += 1;
+    /// Validates a value that is about to be used as a tape address.
+    fn address(&self, address: i64) -> Result<usize, IntcodeError> {
+        if address < 0 {
+            return Err(IntcodeError::NegativeAddress {
+                position: self.position,
+                instruction: self.instruction(),
+                address,
+            });
+        }
+
+        Ok(address as usize)
+    }
+
+    /// Validates the address `offset` cells away from the relative base.
+    fn relative_address(&self, offset: i64) -> Result<usize, IntcodeError> {
+        let address = (self.relative_base as i64).checked_add(offset).ok_or_else(|| self.overflow())?;
+        self.address(address)
+    }
+
+    /// The error for an instruction whose result does not fit in an `i64`.
+    fn overflow(&self) -> IntcodeError {
+        IntcodeError::Overflow { position: self.position, instruction: self.instruction() }
+    }
+
+    /// Reads a tape cell, growing the tape if the address is past its end.
+    fn load(&mut self, pointer: usize) -> Result<i64, IntcodeError> {
+        self.grow(pointer)?;
+        Ok(self.tape.get(pointer))
+    }
+
+    /// Makes room on the tape for `address`, failing if the memory limit does not allow it.
+    fn grow(&mut self, address: usize) -> Result<(), IntcodeError> {
+        let allocated = self.tape.allocated();
+        if !self.tape.grow(address) {
+            return Err(IntcodeError::MemoryLimit {
+                position: self.position,
+                instruction: self.instruction(),
+                address,
+            });
+        }
+        if self.profiling && self.tape.allocated() > allocated {
+            self.profile.record_growth(self.tape.allocated() - allocated);
+        }
+        Ok(())
+    }
+
+    /// Reads the parameter at `offset` cells after the current instruction, resolving it according to its mode.
+    fn fetch_arg(&mut self, mode: ParameterMode, offset: usize) -> Result<i64, IntcodeError> {
+        let parameter = self.load(self.position + offset)?;
 
         let pointer: usize = match mode {
-            ParameterMode::Positional => self.tape[self.position] as usize,
-            ParameterMode::Immediate => self.position,
-            ParameterMode::Relative => (self.relative_base + self.tape[self.position] as isize) as usize,
+            ParameterMode::Positional => self.address(parameter)?,
+            ParameterMode::Immediate => self.position + offset,
+            ParameterMode::Relative => self.relative_address(parameter)?,
         };
 
-        if pointer >= self.tape.len() {
-            self.tape.resize(pointer * 2, 0);
+        let value = self.load(pointer)?;
+        if self.profiling {
+            self.profile.record_read(pointer);
         }
-
-        return self.tape[pointer];
+        if let Some(step) = &mut self.current {
+            step.reads.push(MemoryRead { address: pointer, value });
+        }
+        self.watch(pointer, Access::Read, value, value);
+        Ok(value)
     }
 
-    fn fetch_dest(&mut self, mode: ParameterMode) -> usize {
+    /// Resolves the parameter at `offset` cells after the current instruction to the address it writes to.
+    fn fetch_dest(&mut self, mode: ParameterMode, offset: usize) -> Result<usize, IntcodeError> {
+        let parameter = self.load(self.position + offset)?;
+
         match mode {
-            ParameterMode::Positional | ParameterMode::Immediate => {
-                self.fetch_arg(ParameterMode::Immediate) as usize
-            }
-            ParameterMode::Relative => {
-                let arg = self.fetch_arg(ParameterMode::Immediate) as isize;
-                (self.relative_base + arg) as usize
-            }
+            ParameterMode::Positional => self.address(parameter),
+            ParameterMode::Immediate => Err(IntcodeError::ImmediateWrite {
+                position: self.position,
+                instruction: self.instruction(),
+            }),
+            ParameterMode::Relative => self.relative_address(parameter),
+        }
+    }
+
+    fn store(&mut self, dest: usize, value: i64) -> Result<(), IntcodeError> {
+        self.grow(dest)?;
+        if self.profiling {
+            self.profile.record_write(dest);
+        }
+        let old = self.tape.get(dest);
+        if let Some(step) = &mut self.current {
+            step.writes.push(MemoryWrite { address: dest, old, new: value });
+        }
+        self.watch(dest, Access::Write, old, value);
+        if self.detect_loops {
+            self.tape_hash = self.tape_hash.wrapping_sub(cell_hash(dest, old)).wrapping_add(cell_hash(dest, value));
         }
+        self.tape.set(dest, value);
+        self.invalidate(dest);
+        Ok(())
     }
 
-    fn store(&mut self, dest: usize, value: i64) {
-        if dest >= self.tape.len() {
-            self.tape.resize(dest * 2, 0);
+    /// Moves the instruction pointer to `target`, which must lie inside the tape.
+    fn jump(&mut self, target: i64) -> Result<(), IntcodeError> {
+        if target < 0 || target as usize >= self.tape.len() {
+            return Err(IntcodeError::JumpOutOfRange {
+                position: self.position,
+                instruction: self.instruction(),
+                target,
+            });
         }
-        self.tape[dest] = value;
+
+        self.position = target as usize;
+        Ok(())
     }
 
     /// Add instruction, opcode 1.
     /// Adds together numbers read from two positions and stores the result in a third position.
     /// For example, if your Intcode computer encounters 1,10,20,30, it should read the values at positions 10 and 20,
     /// add those values, and then overwrite the value at position 30 with their sum.
-    fn add(&mut self) {
-        let (mode3, mode2, mode1) = self.fetch3modes();
-        let a = self.fetch_arg(mode1);
-        let b = self.fetch_arg(mode2);
-        let dest = self.fetch_dest(mode3);
-
-        let result = a + b;
-        self.store(dest, result);
-        self.position += 1;
+    fn add(&mut self) -> Result<(), IntcodeError> {
+        let (mode3, mode2, mode1) = self.fetch3modes()?;
+        let a = self.fetch_arg(mode1, 1)?;
+        let b = self.fetch_arg(mode2, 2)?;
+        let dest = self.fetch_dest(mode3, 3)?;
+
+        let result = a.checked_add(b).ok_or_else(|| self.overflow())?;
+        self.store(dest, result)?;
+        self.position += 4;
+        Ok(())
     }
 
     /// Multiply instruction, opcode 2.
     /// Multiplies the two inputs it receives and store the result in the third position.
-    fn mul(&mut self) {
-        let (mode3, mode2, mode1) = self.fetch3modes();
-        let a = self.fetch_arg(mode1);
-        let b = self.fetch_arg(mode2);
-        let dest = self.fetch_dest(mode3);
-
-        let result = a * b;
-        self.store(dest, result);
-        self.position += 1;
+    fn mul(&mut self) -> Result<(), IntcodeError> {
+        let (mode3, mode2, mode1) = self.fetch3modes()?;
+        let a = self.fetch_arg(mode1, 1)?;
+        let b = self.fetch_arg(mode2, 2)?;
+        let dest = self.fetch_dest(mode3, 3)?;
+
+        let result = a.checked_mul(b).ok_or_else(|| self.overflow())?;
+        self.store(dest, result)?;
+        self.position += 4;
+        Ok(())
     }
 
     /// Store instruction, opcode 3.
     /// Takes a single integer as input and saves it to the position given by its only parameter.
     /// For example, the instruction 3,50 would take an input value and store it at address 50.
-    fn st(&mut self) {
-        let mode = self.fetch1mode();
-        let dest = self.fetch_dest(mode);
+    fn st(&mut self) -> Result<(), IntcodeError> {
+        let mode = self.fetch1mode()?;
+        let dest = self.fetch_dest(mode, 1)?;
 
-        if let Some(input) = self.input.pop_front() {
-            self.store(dest, input);
-            self.position += 1;
+        if let Some(input) = self.input.pop_front().or_else(|| self.source.read()) {
+            if let Some(step) = &mut self.current {
+                step.input = Some(input);
+            }
+            self.store(dest, input)?;
+            self.position += 2;
         } else {
             // This instruction should be executed again when input is available.
             self.status = MachineStatus::Yield;
-            self.position -= 1;
         }
+        Ok(())
     }
 
     /// Load instruction, opcode 4.
     /// Outputs the value of its only parameter.
     /// For example, the instruction 4,50 would output the value at address 50.
-    fn ld(&mut self) {
-        let mode = self.fetch1mode();
-        let output = self.fetch_arg(mode);
+    fn ld(&mut self) -> Result<(), IntcodeError> {
+        let mode = self.fetch1mode()?;
+        let output = self.fetch_arg(mode, 1)?;
 
-        self.output.push(output);
-        self.position += 1;
+        if let Some(step) = &mut self.current {
+            step.output = Some(output);
+        }
+        self.output.write(output);
+        self.buffered_output += 1;
+        self.position += 2;
+        Ok(())
     }
 
     /// Jump if not zero instruction, opcode 5.
     /// If the first parameter is non-zero, it sets the instruction pointer
     /// to the value from the second parameter. Otherwise, it does nothing.
-    fn jnz(&mut self) {
-        let (mode2, mode1) = self.fetch2modes();
-        let a = self.fetch_arg(mode1);
-        let b = self.fetch_arg(mode2) as usize;
+    fn jnz(&mut self) -> Result<(), IntcodeError> {
+        let (mode2, mode1) = self.fetch2modes()?;
+        let a = self.fetch_arg(mode1, 1)?;
+        let b = self.fetch_arg(mode2, 2)?;
 
         if a != 0 {
-            self.position = b;
+            self.jump(b)
         } else {
-            self.position += 1;
+            self.position += 3;
+            Ok(())
         }
     }
 
     /// Jump if zero instruction, opcode 6.
     /// If the first parameter is zero, it sets the instruction pointer
     /// to the value from the second parameter. Otherwise, it does nothing.
-    fn jz(&mut self) {
-        let (mode2, mode1) = self.fetch2modes();
-        let a = self.fetch_arg(mode1);
-        let b = self.fetch_arg(mode2) as usize;
+    fn jz(&mut self) -> Result<(), IntcodeError> {
+        let (mode2, mode1) = self.fetch2modes()?;
+        let a = self.fetch_arg(mode1, 1)?;
+        let b = self.fetch_arg(mode2, 2)?;
 
         if a == 0 {
-            self.position = b;
+            self.jump(b)
         } else {
-            self.position += 1;
+            self.position += 3;
+            Ok(())
         }
     }
 
     /// Test if less than instruction, opcode 7.
     /// If the first parameter is less than the second parameter, it stores 1 in the position given
     /// by the third parameter. Otherwise, it stores 0.
-    fn tlt(&mut self) {
-        let (mode3, mode2, mode1) = self.fetch3modes();
-        let a = self.fetch_arg(mode1);
-        let b = self.fetch_arg(mode2);
-        let dest = self.fetch_dest(mode3);
+    fn tlt(&mut self) -> Result<(), IntcodeError> {
+        let (mode3, mode2, mode1) = self.fetch3modes()?;
+        let a = self.fetch_arg(mode1, 1)?;
+        let b = self.fetch_arg(mode2, 2)?;
+        let dest = self.fetch_dest(mode3, 3)?;
 
         let result = if a < b { 1 } else { 0 };
-        self.store(dest, result);
-        self.position += 1;
+        self.store(dest, result)?;
+        self.position += 4;
+        Ok(())
     }
 
     /// Test if equals instruction, opcode 8.
     /// If the first parameter is equal to the second parameter, it stores 1 in the position given
     /// by the third parameter. Otherwise, it stores 0.
-    fn teq(&mut self) {
-        let (mode3, mode2, mode1) = self.fetch3modes();
-        let a = self.fetch_arg(mode1);
-        let b = self.fetch_arg(mode2);
-        let dest = self.fetch_dest(mode3);
+    fn teq(&mut self) -> Result<(), IntcodeError> {
+        let (mode3, mode2, mode1) = self.fetch3modes()?;
+        let a = self.fetch_arg(mode1, 1)?;
+        let b = self.fetch_arg(mode2, 2)?;
+        let dest = self.fetch_dest(mode3, 3)?;
 
         let result = if a == b { 1 } else { 0 };
-        self.store(dest, result);
-        self.position += 1;
+        self.store(dest, result)?;
+        self.position += 4;
+        Ok(())
     }
 
     /// Relative base adjustment instruction, opcode 9.
@@ -248,67 +558,284 @@ impl IntcodeMachine {
     /// The relative base increases (or decreases, if the value is negative) by the value of the parameter.
     /// For example, if the relative base is 2000, then after the instruction 109,19, the relative base would be 2019.
     /// If the next instruction were 204,-34, then the value at address 1985 would be output.
-    fn rel(&mut self) {
-        let mode = self.fetch1mode();
-        let base = self.fetch_arg(mode);
-
-        self.relative_base += base as isize;
-        self.position += 1;
+    fn rel(&mut self) -> Result<(), IntcodeError> {
+        let mode = self.fetch1mode()?;
+        let base = self.fetch_arg(mode, 1)?;
+
+        let old = self.relative_base;
+        self.relative_base = old.checked_add(base as isize).ok_or_else(|| self.overflow())?;
+        if let Some(step) = &mut self.current {
+            step.relative_base = Some((old, self.relative_base));
+        }
+        self.position += 2;
+        Ok(())
     }
 
     /// Halt instruction, opcode 99.
     /// This instruction signals end of execution and that the machine should exit immediately.
-    fn halt(&mut self) {
+    fn halt(&mut self) -> Result<(), IntcodeError> {
         self.status = MachineStatus::Halt;
+        Ok(())
+    }
+
+    /// Decodes and executes the instruction under the instruction pointer.
+    /// On error the instruction pointer is left on the faulting instruction.
+    fn execute(&mut self) -> Result<(), IntcodeError> {
+        let position = self.position;
+        let code = self.instruction() % 100;
+        let opcode = match self.instruction_set.clone() {
+            Some(set) => self.execute_in(&*set, code).map(Some),
+            None => self.execute_builtin(code).map(|()| Opcode::from_code(code)),
+        }?;
+
+        // An instruction waiting for input is executed again once it arrives, count it only then.
+        if self.profiling && self.status != MachineStatus::Yield {
+            self.profile.record_instruction(position, opcode);
+        }
+        if self.status == MachineStatus::Run && !self.watch_hits.is_empty() {
+            self.status = MachineStatus::Watchpoint;
+        }
+        Ok(())
+    }
+
+    fn execute_builtin(&mut self, opcode: i64) -> Result<(), IntcodeError> {
+        match opcode {
+            1 => self.add(),
+            2 => self.mul(),
+            3 => self.st(),
+            4 => self.ld(),
+            5 => self.jnz(),
+            6 => self.jz(),
+            7 => self.tlt(),
+            8 => self.teq(),
+            9 => self.rel(),
+            99 => self.halt(),
+            _ => Err(IntcodeError::UnknownOpcode {
+                position: self.position,
+                instruction: self.instruction(),
+            }),
+        }
+    }
+
+    pub fn status(&self) -> MachineStatus {
+        self.status
+    }
+
+    /// Address of the next instruction to execute.
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    pub fn relative_base(&self) -> isize {
+        self.relative_base
+    }
+
+    pub fn memory(&self) -> &M {
+        &self.tape
+    }
+
+    /// Value of a single cell. Cells past the end of the tape read as zero.
+    pub fn peek(&self, address: usize) -> i64 {
+        self.tape.get(address)
+    }
+
+    /// Input queued but not yet consumed by a `st` instruction.
+    pub fn pending_input(&self) -> impl Iterator<Item = i64> + '_ {
+        self.input.iter().copied()
     }
 
     pub fn halted(&self) -> bool {
-        return self.status == MachineStatus::Halt;
+        self.status == MachineStatus::Halt
     }
 
     pub fn yielded(&self) -> bool {
-        return self.status == MachineStatus::Yield;
+        self.status == MachineStatus::Yield
     }
 
-    pub fn has_output(&self) -> bool {
-        return self.output.len() > 0;
+    /// Whether the machine is done running for good: it halted, used up its instruction budget
+    /// or was caught in a loop. Running it again only stops with the same status, until the instruction
+    /// limit is set again. Anything driving machines in turns should leave it alone from then on.
+    pub fn stopped(&self) -> bool {
+        matches!(self.status, MachineStatus::Halt | MachineStatus::OutOfBudget | MachineStatus::Loop)
     }
 
-    pub fn run(&mut self) -> Vec<i64> {
-        self.run_for_target(0);
-        return self.output.clone();
+    /// Runs until the machine halts, waits for input, reaches a breakpoint or accesses a watched cell,
+    /// returning the value left at `target`.
+    /// A machine stopped at a breakpoint resumes by executing the instruction it stopped on.
+    /// Panics if the program is malformed, see `try_run_for_target` for a non-panicking version.
+    pub fn run_for_target(&mut self, target: usize) -> i64 {
+        self.try_run_for_target(target).unwrap_or_else(|error| panic!("{}", error))
     }
 
-    pub fn run_for_target(&mut self, target: usize) -> i64 {
+    /// Same as `run_for_target`, but returns an error instead of panicking when the program is malformed.
+    /// The machine is left on the faulting instruction, with everything it did before it intact.
+    pub fn try_run_for_target(&mut self, target: usize) -> Result<i64, IntcodeError> {
+        self.output.start_run();
+        self.buffered_output = 0;
+        self.try_resume()?;
+        Ok(self.tape.get(target))
+    }
+
+    /// Runs until the machine halts, waits for input, reaches a breakpoint or accesses a watched cell,
+    /// returning why it stopped. Unlike `run`, output is not reset first.
+    /// Panics if the program is malformed, see `try_resume` for a non-panicking version.
+    pub fn resume(&mut self) -> MachineStatus {
+        self.try_resume().unwrap_or_else(|error| panic!("{}", error))
+    }
+
+    /// Same as `resume`, but returns an error instead of panicking when the program is malformed.
+    pub fn try_resume(&mut self) -> Result<MachineStatus, IntcodeError> {
+        self.run_while(|_| true)
+    }
+
+    /// Runs like `try_resume`, but also stops with a `Run` status as soon as `keep_going` returns false
+    /// after an instruction.
+    fn run_while(&mut self, mut keep_going: impl FnMut(&Self) -> bool) -> Result<MachineStatus, IntcodeError> {
+        let mut resuming = self.status == MachineStatus::Breakpoint;
         self.status = MachineStatus::Run;
-        self.output.clear();
+        self.watch_hits.clear();
+        self.loop_check.clear();
+        if self.detect_loops {
+            self.tape_hash = self.tape.state_hash();
+        }
 
         loop {
-            let opcode = self.tape[self.position] % 100;
-            match opcode {
-                1 => self.add(),
-                2 => self.mul(),
-                3 => self.st(),
-                4 => self.ld(),
-                5 => self.jnz(),
-                6 => self.jz(),
-                7 => self.tlt(),
-                8 => self.teq(),
-                9 => self.rel(),
-                99 => self.halt(),
-                _ => panic!("Unknown opcode {} at position {}", opcode, self.position),
+            if !resuming && self.breakpoints.contains(&self.position) {
+                self.status = MachineStatus::Breakpoint;
+                return Ok(self.status);
+            }
+            resuming = false;
+
+            if let Some(status) = self.check_limits() {
+                self.status = status;
+                return Ok(self.status);
+            }
+            self.budget_used += 1;
+
+            if self.tracer.is_some() || self.history_limit > 0 {
+                self.execute_logged()?;
+            } else if self.use_decoded() {
+                self.execute_decoded()?;
+            } else {
+                self.execute()?;
             }
 
-            if self.status == MachineStatus::Halt || self.status == MachineStatus::Yield {
-                return self.tape[target];
+            if self.status != MachineStatus::Run || !keep_going(self) {
+                return Ok(self.status);
             }
         }
     }
 }
 
+impl<I: Input, O: Output> IntcodeMachine<I, O, DenseMemory> {
+    /// The whole tape, including any zeroes appended when the program accessed cells past its end.
+    pub fn tape(&self) -> &[i64] {
+        self.tape.cells()
+    }
+}
+
+impl<I: Input, M: Memory> IntcodeMachine<I, Vec<i64>, M> {
+    /// Output produced since the last call to `run`.
+    pub fn output(&self) -> &[i64] {
+        &self.output
+    }
+
+    pub fn has_output(&self) -> bool {
+        !self.output.is_empty()
+    }
+
+    /// Runs until the machine halts, waits for input, reaches a breakpoint or accesses a watched cell,
+    /// returning the output produced along the way.
+    /// Panics if the program is malformed, see `try_run` for a non-panicking version.
+    pub fn run(&mut self) -> Vec<i64> {
+        self.run_for_target(0);
+        self.output.clone()
+    }
+
+    /// Same as `run`, but returns an error instead of panicking when the program is malformed.
+    pub fn try_run(&mut self) -> Result<Vec<i64>, IntcodeError> {
+        self.try_run_for_target(0)?;
+        Ok(self.output.clone())
+    }
+
+    /// Runs until the next value is output, returning it, or until the machine stops without outputting anything.
+    /// The value is taken out of `output`.
+    pub fn try_next_output(&mut self) -> Result<Option<i64>, IntcodeError> {
+        let length = self.output.len();
+        self.run_while(|machine| machine.output.len() == length)?;
+
+        if self.output.len() > length {
+            self.buffered_output = self.buffered_output.saturating_sub(1);
+            Ok(self.output.pop())
+        } else {
+            Ok(None)
+        }
+    }
+}
+
+/// Programs shared by the tests of several modules.
+#[cfg(test)]
+mod fixtures {
+    use crate::assemble;
+
+    /// Outputs a copy of itself.
+    pub(crate) fn quine() -> Vec<i64> {
+        vec![109, 1, 204, -1, 1001, 100, 1, 100, 1008, 100, 16, 101, 1006, 101, 0, 99]
+    }
+
+    /// Reads two numbers and outputs their sum.
+    pub(crate) fn echo_sum_program() -> Vec<i64> {
+        vec![3, 11, 3, 12, 1, 11, 12, 13, 4, 13, 99, 0, 0, 0]
+    }
+
+    /// Stores 7 at address 1,000,000,000, reads it back and outputs it.
+    pub(crate) fn far_write_program() -> Vec<i64> {
+        vec![1101, 3, 4, 1_000_000_000, 4, 1_000_000_000, 99]
+    }
+
+    /// Reads a number, outputs 999 if it is below 8, 1000 if it is 8 and 1001 if it is above.
+    pub(crate) fn compare_program() -> Vec<i64> {
+        vec![3, 21, 1008, 21, 8, 20, 1005, 20, 22, 107, 8, 21, 20, 1006, 20, 31,
+             1106, 0, 36, 98, 0, 0, 1002, 21, 125, 20, 4, 20, 1105, 1, 46, 104,
+             999, 1105, 1, 46, 1101, 1000, 1, 20, 4, 20, 1105, 1, 46, 98, 99]
+    }
+
+    /// Reads a number and outputs every number from it down to 1, with the relative base at 100,
+    /// then grows the tape by writing to address 1000. The counter is at address 18.
+    pub(crate) fn countdown_program() -> Vec<i64> {
+        assemble("
+            st [counter]
+            rel #100
+        loop:
+            ld [counter]
+            add [counter], #-1, [counter]
+            jnz [counter], #loop
+            add #1, #1, [1000]
+            halt
+        counter: .data 0
+        ").unwrap()
+    }
+
+    /// Counts from 1 to `target`, then outputs it. The counter is at address 14, the flag set
+    /// when it reaches the target at address 15.
+    pub(crate) fn counter_program(target: i64) -> Vec<i64> {
+        assemble(&format!("
+        loop:
+            add [counter], #1, [counter]
+            teq [counter], #{}, [done]
+            jz [done], #loop
+            ld [counter]
+            halt
+        counter: .data 0
+        done: .data 0
+        ", target)).unwrap()
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use super::IntcodeMachine;
+    use super::{IntcodeError, IntcodeMachine};
+    use crate::fixtures::quine;
 
     #[test]
     fn test_mul_should_output_3500() {
@@ -545,7 +1072,7 @@ mod tests {
 
     #[test]
     fn test_quine() {
-        let tape: Vec<i64> = vec![109, 1, 204, -1, 1001, 100, 1, 100, 1008, 100, 16, 101, 1006, 101, 0, 99];
+        let tape = quine();
         let mut machine = IntcodeMachine::new(tape.clone());
 
         machine.run();
@@ -569,4 +1096,95 @@ mod tests {
         machine.run();
         assert_eq!(machine.output[0], 1125899906842624);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_unknown_opcode_is_an_error() {
+        let tape: Vec<i64> = vec![1, 0, 0, 0, 42, 99];
+        let mut machine = IntcodeMachine::new(tape);
+
+        assert_eq!(machine.try_run(), Err(IntcodeError::UnknownOpcode { position: 4, instruction: 42 }));
+        assert_eq!(machine.tape()[0], 2);
+    }
+
+    #[test]
+    #[should_panic(expected = "Unknown opcode 42 at position 4")]
+    fn test_unknown_opcode_panics() {
+        let tape: Vec<i64> = vec![1, 0, 0, 0, 42, 99];
+        let mut machine = IntcodeMachine::new(tape);
+
+        machine.run();
+    }
+
+    #[test]
+    fn test_invalid_parameter_mode_is_an_error() {
+        let tape: Vec<i64> = vec![1301, 0, 0, 0, 99];
+        let mut machine = IntcodeMachine::new(tape);
+
+        assert_eq!(machine.try_run(), Err(IntcodeError::InvalidParameterMode { position: 0, instruction: 1301, mode: 3 }));
+    }
+
+    #[test]
+    fn test_negative_address_is_an_error() {
+        let tape: Vec<i64> = vec![4, -1, 99];
+        let mut machine = IntcodeMachine::new(tape);
+
+        assert_eq!(machine.try_run(), Err(IntcodeError::NegativeAddress { position: 0, instruction: 4, address: -1 }));
+    }
+
+    #[test]
+    fn test_negative_relative_address_is_an_error() {
+        let tape: Vec<i64> = vec![109, -5, 204, 1, 99];
+        let mut machine = IntcodeMachine::new(tape);
+
+        assert_eq!(machine.try_run(), Err(IntcodeError::NegativeAddress { position: 2, instruction: 204, address: -4 }));
+    }
+
+    #[test]
+    fn test_immediate_write_is_an_error() {
+        let tape: Vec<i64> = vec![11101, 1, 1, 0, 99];
+        let mut machine = IntcodeMachine::new(tape);
+
+        assert_eq!(machine.try_run(), Err(IntcodeError::ImmediateWrite { position: 0, instruction: 11101 }));
+    }
+
+    #[test]
+    fn test_jump_out_of_range_is_an_error() {
+        let tape: Vec<i64> = vec![1105, 1, 100, 99];
+        let mut machine = IntcodeMachine::new(tape);
+
+        let error = machine.try_run().unwrap_err();
+        assert_eq!(error, IntcodeError::JumpOutOfRange { position: 0, instruction: 1105, target: 100 });
+        assert_eq!(error.position(), 0);
+        assert_eq!(error.instruction(), 1105);
+    }
+
+    #[test]
+    fn test_overflow_is_an_error() {
+        let tape: Vec<i64> = vec![1101, i64::MAX, 1, 0, 99];
+        let mut machine = IntcodeMachine::new(tape);
+        assert_eq!(machine.try_run(), Err(IntcodeError::Overflow { position: 0, instruction: 1101 }));
+        assert_eq!(machine.peek(0), 1101);
+
+        let tape: Vec<i64> = vec![109, i64::MAX, 109, 1, 99];
+        let mut machine = IntcodeMachine::new(tape);
+        assert_eq!(machine.try_run(), Err(IntcodeError::Overflow { position: 2, instruction: 109 }));
+        assert_eq!(machine.relative_base, i64::MAX as isize);
+    }
+
+    #[test]
+    fn test_state_accessors() {
+        let tape: Vec<i64> = vec![109, 5, 3, 0, 104, 7, 99];
+        let mut machine = IntcodeMachine::new(tape)
+            .with_input(1)
+            .with_input(2);
+
+        machine.run_for_target(0);
+        assert_eq!(machine.position(), 6);
+        assert_eq!(machine.relative_base(), 5);
+        assert_eq!(machine.peek(0), 1);
+        assert_eq!(machine.peek(100), 0);
+        assert_eq!(machine.tape().len(), 7);
+        assert_eq!(machine.pending_input().collect::<Vec<i64>>(), vec![2]);
+        assert_eq!(machine.output(), &[7]);
+    }
+}
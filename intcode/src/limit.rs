@@ -0,0 +1,239 @@
+use crate::{Input, IntcodeMachine, MachineStatus, Memory, Output, Segment};
+
+/// A state of the machine: position, relative base and the contents of the tape.
+#[derive(Debug, Clone)]
+struct State {
+    position: usize,
+    relative_base: isize,
+    tape_hash: u64,
+    tape: Vec<Segment>,
+}
+
+impl State {
+    /// Value of the cell at `address` in the saved tape.
+    fn get(&self, address: usize) -> i64 {
+        let index = self.tape.partition_point(|&(start, _)| start <= address);
+        match index.checked_sub(1).map(|index| &self.tape[index]) {
+            Some((start, values)) if address < start + values.len() => values[address - start],
+            _ => 0,
+        }
+    }
+
+    /// Whether the tape holds exactly the same values as the saved one. Cells missing from either read as zero.
+    fn same_tape<M: Memory>(&self, tape: &M) -> bool {
+        let matches = |start: usize, values: &[i64], get: &dyn Fn(usize) -> i64| {
+            values.iter().enumerate().all(|(offset, &value)| get(start + offset) == value)
+        };
+        self.tape.iter().all(|(start, values)| matches(*start, values, &|address| tape.get(address)))
+            && tape.segments().into_iter().all(|(start, values)| matches(start, values, &|address| self.get(address)))
+    }
+}
+
+/// Brent's cycle detection over the states of a machine: the current state is compared with a single saved one,
+/// which is replaced after 1, 2, 4, 8... instructions. A loop is found within a few times its length,
+/// while keeping a single copy of the tape.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct LoopCheck {
+    saved: Option<State>,
+    /// Instructions since the state was saved, and how many to wait before saving the next one.
+    steps: u64,
+    period: u64,
+}
+
+impl LoopCheck {
+    pub(crate) fn clear(&mut self) {
+        *self = LoopCheck::default();
+    }
+}
+
+impl<I: Input, O: Output, M: Memory> IntcodeMachine<I, O, M> {
+    /// Stops `run` with an `OutOfBudget` status once the machine has executed `limit` instructions since the limit
+    /// was set. The machine then stays out of budget, however often it runs, until the limit is set again
+    /// to grant it a fresh budget. `None` removes the limit.
+    pub fn set_instruction_limit(&mut self, limit: Option<u64>) {
+        self.instruction_limit = limit;
+        self.budget_used = 0;
+    }
+
+    pub fn with_instruction_limit(mut self, limit: u64) -> Self {
+        self.set_instruction_limit(Some(limit));
+        self
+    }
+
+    /// Stops `run` with a `Loop` status when the machine is about to repeat an instruction in the exact same
+    /// state, with no input or output since. The tape is hashed once per run, then updated by each write,
+    /// and only compared cell by cell with the saved state when the hashes match.
+    pub fn detect_loops(&mut self, enabled: bool) {
+        self.detect_loops = enabled;
+        self.loop_check.clear();
+    }
+
+    pub fn with_loop_detection(mut self) -> Self {
+        self.detect_loops(true);
+        self
+    }
+
+    /// Whether the machine is in the state saved by the loop check, saving the current state when it is time to.
+    fn repeats_state(&mut self) -> bool {
+        let check = &mut self.loop_check;
+        if let Some(saved) = &check.saved {
+            if saved.position == self.position && saved.relative_base == self.relative_base
+                && saved.tape_hash == self.tape_hash && saved.same_tape(&self.tape) {
+                return true;
+            }
+        }
+
+        if check.saved.is_none() || check.steps == check.period {
+            let tape = self.tape.segments().into_iter().map(|(start, values)| (start, values.to_vec())).collect();
+            check.saved = Some(State { position: self.position, relative_base: self.relative_base, tape_hash: self.tape_hash, tape });
+            check.period = (check.period * 2).max(1);
+            check.steps = 0;
+        }
+        check.steps += 1;
+        false
+    }
+
+    /// The status to stop with before executing the next instruction.
+    pub(crate) fn check_limits(&mut self) -> Option<MachineStatus> {
+        if self.instruction_limit.is_some_and(|limit| self.budget_used >= limit) {
+            return Some(MachineStatus::OutOfBudget);
+        }
+
+        if self.detect_loops {
+            match self.instruction() % 100 {
+                // Input and output make the state observable from outside, repeating it is fine.
+                3 | 4 => self.loop_check.clear(),
+                _ => {
+                    if self.repeats_state() {
+                        return Some(MachineStatus::Loop);
+                    }
+                }
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::State;
+    use crate::fixtures::counter_program;
+    use crate::{assemble, IntcodeMachine, MachineStatus, Memory};
+
+    fn spin_program() -> Vec<i64> {
+        assemble("
+        spin:
+            jz #0, #spin
+        ").unwrap()
+    }
+
+    #[test]
+    fn test_instruction_limit() {
+        let mut machine = IntcodeMachine::new(counter_program(i64::MAX)).with_instruction_limit(100);
+
+        machine.run();
+        assert_eq!(machine.status(), MachineStatus::OutOfBudget);
+        assert_eq!(machine.peek(14), 34);
+        assert!(machine.stopped());
+
+        machine.run();
+        assert_eq!(machine.status(), MachineStatus::OutOfBudget);
+        assert_eq!(machine.peek(14), 34);
+
+        machine.set_instruction_limit(Some(100));
+        machine.run();
+        assert_eq!(machine.peek(14), 67);
+    }
+
+    #[test]
+    fn test_limit_does_not_affect_short_runs() {
+        let mut machine = IntcodeMachine::new(vec![1, 9, 10, 3, 2, 3, 11, 0, 99, 30, 40, 50])
+            .with_instruction_limit(3);
+
+        assert_eq!(machine.run_for_target(0), 3500);
+        assert!(machine.halted());
+    }
+
+    #[test]
+    fn test_detect_loop() {
+        let mut machine = IntcodeMachine::new(spin_program()).with_loop_detection();
+
+        machine.run();
+        assert_eq!(machine.status(), MachineStatus::Loop);
+        assert_eq!(machine.position(), 0);
+    }
+
+    #[test]
+    fn test_changing_state_is_not_a_loop() {
+        let mut machine = IntcodeMachine::new(counter_program(i64::MAX))
+            .with_loop_detection()
+            .with_instruction_limit(1000);
+
+        machine.run();
+        assert_eq!(machine.status(), MachineStatus::OutOfBudget);
+    }
+
+    #[test]
+    fn test_loop_after_writes() {
+        // Counts down from 3 to 0, then keeps storing 0 over the counter, which no longer changes anything.
+        let tape = assemble("
+        loop:
+            jz [counter], #zero
+            add [counter], #-1, [counter]
+            jz #0, #loop
+        zero:
+            add #0, #0, [counter]
+            jz #0, #zero
+        counter: .data 3
+        ").unwrap();
+        let mut machine = IntcodeMachine::new(tape).with_loop_detection();
+
+        machine.run();
+        assert_eq!(machine.status(), MachineStatus::Loop);
+        assert_eq!(machine.peek(17), 0);
+    }
+
+    #[test]
+    fn test_long_loop() {
+        // Counts from 0 to 999 over and over, a loop of a few thousand instructions.
+        let tape = assemble("
+        loop:
+            add [counter], #1, [counter]
+            teq [counter], #1000, [wrap]
+            jz [wrap], #loop
+            add #0, #0, [counter]
+            jz #0, #loop
+        counter: .data 0
+        wrap: .data 0
+        ").unwrap();
+        let mut machine = IntcodeMachine::new(tape).with_loop_detection().with_instruction_limit(100_000);
+
+        machine.run();
+        assert_eq!(machine.status(), MachineStatus::Loop);
+    }
+
+    #[test]
+    fn test_matching_hash_is_not_enough() {
+        let mut machine = IntcodeMachine::new(counter_program(i64::MAX)).with_loop_detection();
+        machine.tape_hash = machine.tape.state_hash();
+
+        // A saved state that only differs by its tape, with a colliding hash.
+        let mut tape = machine.tape.segments()[0].1.to_vec();
+        tape[14] += 1;
+        machine.loop_check.saved = Some(State { position: 0, relative_base: 0, tape_hash: machine.tape_hash, tape: vec![(0, tape)] });
+        machine.loop_check.period = 4;
+        assert_eq!(machine.check_limits(), None);
+    }
+
+    #[test]
+    fn test_io_resets_loop_detection() {
+        // Echoes its input forever, a loop broken by every input.
+        let mut machine = IntcodeMachine::new(vec![3, 5, 4, 5, 1105, 1, 0])
+            .with_loop_detection()
+            .with_input(1)
+            .with_input(2);
+
+        assert_eq!(machine.run(), vec![1, 2]);
+        assert!(machine.yielded());
+    }
+}
@@ -0,0 +1,301 @@
+use std::collections::BTreeMap;
+
+/// A stretch of consecutive cells, with the address of the first one.
+pub type Segment = (usize, Vec<i64>);
+
+/// Storage for the tape of a machine. Cells that were never written read as zero.
+pub trait Memory {
+    /// Number of addressable cells: the program, plus whatever `grow` added to reach the addresses accessed since.
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn get(&self, address: usize) -> i64;
+
+    /// Overwrites a cell, which should have been made addressable with `grow` first.
+    fn set(&mut self, address: usize, value: i64);
+
+    /// Makes `address` addressable before it is accessed, allocating storage for it if needed.
+    /// Returns false, leaving the memory untouched, when that would allocate more cells than the limit allows.
+    fn grow(&mut self, address: usize) -> bool;
+
+    /// Forgets every cell from `length` on, making the memory `length` cells long.
+    fn truncate(&mut self, length: usize);
+
+    /// Caps the number of cells that can be allocated. `None` removes the cap.
+    fn set_limit(&mut self, cells: Option<usize>);
+
+    /// Number of cells currently allocated.
+    fn allocated(&self) -> usize;
+
+    /// The stretches of cells actually stored, by address of their first cell. Every other cell is zero.
+    fn segments(&self) -> Vec<(usize, &[i64])>;
+
+    /// Replaces the whole memory with `length` cells, all zero but those in `segments`, keeping the limit.
+    /// Returns false, leaving the memory untouched, when that would allocate more cells than the limit allows.
+    fn load(&mut self, length: usize, segments: &[Segment]) -> bool;
+
+    /// Hash of the contents of the memory, the wrapping sum of `cell_hash` over its nonzero cells,
+    /// so that loop detection can keep it up to date one write at a time.
+    fn state_hash(&self) -> u64 {
+        (0..self.len()).fold(0, |hash, address| hash.wrapping_add(cell_hash(address, self.get(address))))
+    }
+}
+
+/// Contribution of a cell to `Memory::state_hash`. Zero cells contribute nothing,
+/// so growing the memory does not change its hash.
+pub fn cell_hash(address: usize, value: i64) -> u64 {
+    if value == 0 {
+        return 0;
+    }
+    // The SplitMix64 finalizer, spreading every bit of the address and value over the result.
+    let mut hash = (address as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15) ^ value as u64;
+    hash = (hash ^ (hash >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    hash = (hash ^ (hash >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    hash ^ (hash >> 31)
+}
+
+/// A single contiguous tape, doubled in size whenever an access falls past its end.
+/// Fast, but far away addresses allocate every cell before them.
+#[derive(Debug, Clone, Default, Eq, PartialEq, Hash)]
+pub struct DenseMemory {
+    cells: Vec<i64>,
+    limit: Option<usize>,
+}
+
+impl DenseMemory {
+    pub fn new(tape: Vec<i64>) -> DenseMemory {
+        DenseMemory { cells: tape, limit: None }
+    }
+
+    pub fn with_limit(mut self, cells: usize) -> Self {
+        self.set_limit(Some(cells));
+        self
+    }
+
+    pub fn cells(&self) -> &[i64] {
+        &self.cells
+    }
+}
+
+impl Memory for DenseMemory {
+    fn len(&self) -> usize {
+        self.cells.len()
+    }
+
+    fn get(&self, address: usize) -> i64 {
+        self.cells.get(address).copied().unwrap_or(0)
+    }
+
+    fn set(&mut self, address: usize, value: i64) {
+        if address >= self.cells.len() {
+            self.cells.resize(address + 1, 0);
+        }
+        self.cells[address] = value;
+    }
+
+    fn grow(&mut self, address: usize) -> bool {
+        if address < self.cells.len() {
+            return true;
+        }
+        let length = match self.limit {
+            Some(limit) if address >= limit => return false,
+            Some(limit) => (address * 2).min(limit),
+            None => address * 2,
+        };
+        self.cells.resize(length.max(address + 1), 0);
+        true
+    }
+
+    fn truncate(&mut self, length: usize) {
+        self.cells.truncate(length);
+    }
+
+    fn set_limit(&mut self, cells: Option<usize>) {
+        self.limit = cells;
+    }
+
+    fn allocated(&self) -> usize {
+        self.cells.len()
+    }
+
+    fn segments(&self) -> Vec<(usize, &[i64])> {
+        vec![(0, &self.cells)]
+    }
+
+    fn load(&mut self, length: usize, segments: &[Segment]) -> bool {
+        if self.limit.is_some_and(|limit| length > limit) {
+            return false;
+        }
+        self.cells.clear();
+        self.cells.resize(length, 0);
+        for (start, values) in segments {
+            self.cells[*start..start + values.len()].copy_from_slice(values);
+        }
+        true
+    }
+}
+
+/// Number of cells in each page of a `PagedMemory`.
+const PAGE_SIZE: usize = 1024;
+
+/// A tape split in fixed size pages, allocated only when an address inside them is accessed.
+/// Slower than `DenseMemory`, but programs using a few far away addresses only cost a page each.
+#[derive(Debug, Clone, Default, Eq, PartialEq, Hash)]
+pub struct PagedMemory {
+    /// Pages by index, page `n` holding the cells from `n * PAGE_SIZE`.
+    pages: BTreeMap<usize, Box<[i64]>>,
+    length: usize,
+    limit: Option<usize>,
+}
+
+impl PagedMemory {
+    pub fn new(tape: Vec<i64>) -> PagedMemory {
+        let mut memory = PagedMemory { pages: BTreeMap::new(), length: tape.len(), limit: None };
+        for (address, value) in tape.into_iter().enumerate() {
+            memory.set(address, value);
+        }
+        memory
+    }
+
+    pub fn with_limit(mut self, cells: usize) -> Self {
+        self.set_limit(Some(cells));
+        self
+    }
+
+    fn page(&mut self, address: usize) -> &mut [i64] {
+        self.pages.entry(address / PAGE_SIZE).or_insert_with(|| vec![0; PAGE_SIZE].into_boxed_slice())
+    }
+}
+
+impl Memory for PagedMemory {
+    fn len(&self) -> usize {
+        self.length
+    }
+
+    fn get(&self, address: usize) -> i64 {
+        self.pages.get(&(address / PAGE_SIZE)).map_or(0, |page| page[address % PAGE_SIZE])
+    }
+
+    fn set(&mut self, address: usize, value: i64) {
+        self.length = self.length.max(address + 1);
+        self.page(address)[address % PAGE_SIZE] = value;
+    }
+
+    fn grow(&mut self, address: usize) -> bool {
+        if !self.pages.contains_key(&(address / PAGE_SIZE)) {
+            if self.limit.is_some_and(|limit| self.allocated() + PAGE_SIZE > limit) {
+                return false;
+            }
+            self.page(address);
+        }
+        self.length = self.length.max(address + 1);
+        true
+    }
+
+    fn truncate(&mut self, length: usize) {
+        self.length = self.length.min(length);
+        self.pages.retain(|&index, _| index * PAGE_SIZE < length);
+        if let Some(page) = self.pages.get_mut(&(length / PAGE_SIZE)) {
+            page[length % PAGE_SIZE..].iter_mut().for_each(|cell| *cell = 0);
+        }
+    }
+
+    fn set_limit(&mut self, cells: Option<usize>) {
+        self.limit = cells;
+    }
+
+    fn allocated(&self) -> usize {
+        self.pages.len() * PAGE_SIZE
+    }
+
+    fn segments(&self) -> Vec<(usize, &[i64])> {
+        self.pages.iter()
+            .map(|(index, page)| {
+                let start = index * PAGE_SIZE;
+                (start, &page[..PAGE_SIZE.min(self.length - start)])
+            })
+            .collect()
+    }
+
+    fn load(&mut self, length: usize, segments: &[Segment]) -> bool {
+        let mut loaded = PagedMemory { pages: BTreeMap::new(), length, limit: self.limit };
+        for (start, values) in segments {
+            for (offset, &value) in values.iter().enumerate().filter(|&(_, &value)| value != 0) {
+                if !loaded.grow(start + offset) {
+                    return false;
+                }
+                loaded.set(start + offset, value);
+            }
+        }
+        loaded.length = length;
+        *self = loaded;
+        true
+    }
+
+    fn state_hash(&self) -> u64 {
+        self.pages.iter()
+            .flat_map(|(index, page)| page.iter().enumerate().map(move |(offset, &value)| (index * PAGE_SIZE + offset, value)))
+            .fold(0, |hash, (address, value)| hash.wrapping_add(cell_hash(address, value)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{DenseMemory, Memory, PagedMemory, PAGE_SIZE};
+    use crate::fixtures::far_write_program;
+    use crate::{IntcodeError, IntcodeMachine};
+
+    #[test]
+    fn test_paged_memory_reads_unwritten_cells_as_zero() {
+        let mut memory = PagedMemory::new(vec![1, 2, 3]);
+        assert_eq!(memory.len(), 3);
+        assert_eq!(memory.get(1), 2);
+        assert_eq!(memory.get(5000), 0);
+
+        assert!(memory.grow(5000));
+        memory.set(5000, 9);
+        assert_eq!(memory.len(), 5001);
+        assert_eq!(memory.get(5000), 9);
+        assert_eq!(memory.allocated(), 2 * PAGE_SIZE);
+
+        memory.truncate(2);
+        assert_eq!(memory.get(2), 0);
+        assert_eq!(memory.get(5000), 0);
+        assert_eq!(memory.allocated(), PAGE_SIZE);
+    }
+
+    #[test]
+    fn test_far_address_with_paged_memory() {
+        let mut machine = IntcodeMachine::from_memory(PagedMemory::new(far_write_program()));
+
+        assert_eq!(machine.run(), vec![7]);
+        assert_eq!(machine.memory().allocated(), 2 * PAGE_SIZE);
+    }
+
+    #[test]
+    fn test_memory_limit() {
+        let mut dense = IntcodeMachine::new(far_write_program()).with_memory_limit(1 << 20);
+        let mut paged = IntcodeMachine::from_memory(PagedMemory::new(far_write_program()).with_limit(PAGE_SIZE));
+
+        for error in [dense.try_run().unwrap_err(), paged.try_run().unwrap_err()] {
+            assert_eq!(error, IntcodeError::MemoryLimit {
+                position: 0,
+                instruction: 1101,
+                address: 1_000_000_000,
+            });
+        }
+        assert_eq!(dense.memory().allocated(), 7);
+    }
+
+    #[test]
+    fn test_dense_growth_is_capped_by_limit() {
+        let mut memory = DenseMemory::new(vec![0; 10]).with_limit(15);
+
+        assert!(memory.grow(12));
+        assert_eq!(memory.len(), 15);
+        assert!(!memory.grow(15));
+    }
+}
@@ -0,0 +1,258 @@
+use std::error::Error;
+use std::fmt;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread::{self, JoinHandle};
+
+use crate::{Input, IntcodeError, IntcodeMachine, MachineStatus, Output};
+
+/// Blocks until a value arrives. Once every sender is gone the machine yields instead.
+impl Input for Receiver<i64> {
+    fn read(&mut self) -> Option<i64> {
+        self.recv().ok()
+    }
+}
+
+/// Values sent after the receiver is gone are dropped.
+impl Output for Sender<i64> {
+    fn write(&mut self, value: i64) {
+        self.send(value).ok();
+    }
+}
+
+/// Errors raised when linking machines into a network.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum NetworkError {
+    /// A `(from, to)` link names a machine that is not in the network.
+    InvalidLink(usize, usize),
+    /// A machine on its own thread feeding itself keeps its input open, and would wait for it forever.
+    SelfLink(usize),
+}
+
+impl fmt::Display for NetworkError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            NetworkError::InvalidLink(from, to) => write!(f, "Link from machine {} to machine {} is out of range", from, to),
+            NetworkError::SelfLink(index) => write!(f, "Machine {} cannot feed itself on a thread", index),
+        }
+    }
+}
+
+impl Error for NetworkError {}
+
+/// Checks that every link connects two of the `count` machines of a network.
+fn check_links(count: usize, links: &[(usize, usize)]) -> Result<(), NetworkError> {
+    match links.iter().find(|&&(from, to)| from >= count || to >= count) {
+        Some(&(from, to)) => Err(NetworkError::InvalidLink(from, to)),
+        None => Ok(()),
+    }
+}
+
+/// Sends every output value to several channels.
+struct Fanout(Vec<Sender<i64>>);
+
+impl Output for Fanout {
+    fn write(&mut self, value: i64) {
+        self.0.iter_mut().for_each(|sender| sender.write(value));
+    }
+}
+
+/// A machine running on its own thread.
+pub struct MachineThread {
+    /// Feeds the machine after the input it had queued when spawned.
+    /// Dropping it, along with any link into the machine, lets a machine waiting for input stop with a `Yield` status.
+    pub input: Sender<i64>,
+    /// Everything the machine outputs, whether or not it is also sent along a link.
+    pub output: Receiver<i64>,
+    handle: JoinHandle<Result<MachineStatus, IntcodeError>>,
+}
+
+impl MachineThread {
+    /// Waits for the machine to stop, returning why it did.
+    pub fn join(self) -> Result<MachineStatus, IntcodeError> {
+        self.handle.join().unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+    }
+}
+
+impl IntcodeMachine {
+    /// Runs the machine on a new thread until it halts, reaches a breakpoint or watchpoint,
+    /// or waits for input that can no longer come.
+    pub fn spawn(self) -> MachineThread {
+        spawn_linked(vec![self], &[]).pop().unwrap()
+    }
+}
+
+/// Links `count` machines in a ring, each one feeding the next and the last one feeding the first.
+pub fn ring(count: usize) -> Vec<(usize, usize)> {
+    (0..count).map(|from| (from, (from + 1) % count)).collect()
+}
+
+/// Spawns each machine on its own thread. For every `(from, to)` link, the output of machine `from`
+/// is sent to the input of machine `to`. A machine may feed any number of other machines;
+/// links from a machine to itself are rejected, use a `Scheduler` to run those.
+pub fn spawn_network(machines: Vec<IntcodeMachine>, links: &[(usize, usize)]) -> Result<Vec<MachineThread>, NetworkError> {
+    check_links(machines.len(), links)?;
+    if let Some(&(index, _)) = links.iter().find(|&&(from, to)| from == to) {
+        return Err(NetworkError::SelfLink(index));
+    }
+    Ok(spawn_linked(machines, links))
+}
+
+fn spawn_linked(machines: Vec<IntcodeMachine>, links: &[(usize, usize)]) -> Vec<MachineThread> {
+    let (inputs, sources): (Vec<_>, Vec<_>) = machines.iter().map(|_| mpsc::channel()).unzip();
+
+    machines.into_iter()
+        .zip(sources)
+        .enumerate()
+        .map(|(index, (machine, source))| {
+            let (output, receiver) = mpsc::channel();
+            let mut sink = vec![output];
+            sink.extend(links.iter()
+                .filter(|&&(from, _)| from == index)
+                .map(|&(_, to)| inputs[to].clone()));
+
+            let mut machine = machine.with_source(source).with_sink(Fanout(sink));
+            MachineThread {
+                input: inputs[index].clone(),
+                output: receiver,
+                handle: thread::spawn(move || machine.try_resume()),
+            }
+        })
+        .collect()
+}
+
+/// Runs linked machines in turns on the current thread, so networks can be tested deterministically.
+pub struct Scheduler {
+    machines: Vec<IntcodeMachine>,
+    links: Vec<(usize, usize)>,
+    outputs: Vec<Vec<i64>>,
+}
+
+impl Scheduler {
+    /// Links machines the same way as `spawn_network`, machines feeding themselves included.
+    pub fn new(machines: Vec<IntcodeMachine>, links: &[(usize, usize)]) -> Result<Scheduler, NetworkError> {
+        check_links(machines.len(), links)?;
+        let outputs = vec![vec![]; machines.len()];
+        Ok(Scheduler { machines, links: links.to_vec(), outputs })
+    }
+
+    pub fn machine(&self, index: usize) -> &IntcodeMachine {
+        &self.machines[index]
+    }
+
+    pub fn machine_mut(&mut self, index: usize) -> &mut IntcodeMachine {
+        &mut self.machines[index]
+    }
+
+    /// Everything machine `index` has output so far.
+    pub fn output(&self, index: usize) -> &[i64] {
+        &self.outputs[index]
+    }
+
+    /// Runs every machine that can make progress in turn, from the first to the last, passing output along links,
+    /// until all machines are stopped or waiting for input nobody is going to send.
+    pub fn run(&mut self) -> Result<(), IntcodeError> {
+        loop {
+            let mut progressed = false;
+
+            for index in 0..self.machines.len() {
+                let machine = &mut self.machines[index];
+                if machine.stopped() || (machine.yielded() && machine.pending_input().next().is_none()) {
+                    continue;
+                }
+                progressed = true;
+
+                let output = machine.try_run()?;
+                let targets: Vec<usize> = self.links.iter()
+                    .filter(|&&(from, _)| from == index)
+                    .map(|&(_, to)| to)
+                    .collect();
+                for to in targets {
+                    output.iter().for_each(|&value| self.machines[to].add_input(value));
+                }
+                self.outputs[index].extend(output);
+            }
+
+            if !progressed {
+                return Ok(());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ring, spawn_network, MachineThread, NetworkError, Scheduler};
+    use crate::{IntcodeMachine, MachineStatus};
+
+    fn amplifier_program() -> Vec<i64> {
+        vec![3, 26, 1001, 26, -4, 26, 3, 27, 1002, 27, 2, 27, 1, 27, 26,
+             27, 4, 27, 1001, 28, -1, 28, 1005, 28, 6, 99, 0, 0, 5]
+    }
+
+    fn amplifiers() -> Vec<IntcodeMachine> {
+        let mut amplifiers: Vec<IntcodeMachine> = [9, 8, 7, 6, 5].iter()
+            .map(|&phase| IntcodeMachine::new(amplifier_program()).with_input(phase))
+            .collect();
+        amplifiers[0].add_input(0);
+        amplifiers
+    }
+
+    #[test]
+    fn test_spawn() {
+        let machine = IntcodeMachine::new(vec![3, 7, 4, 7, 1105, 1, 0, 0]).with_input(1);
+        let thread = machine.spawn();
+
+        thread.input.send(2).unwrap();
+        assert_eq!(thread.output.recv(), Ok(1));
+        assert_eq!(thread.output.recv(), Ok(2));
+
+        let MachineThread { input, output, handle } = thread;
+        drop(input);
+        assert_eq!(handle.join().unwrap(), Ok(MachineStatus::Yield));
+        assert!(output.recv().is_err());
+    }
+
+    #[test]
+    fn test_feedback_loop_on_threads() {
+        let threads = spawn_network(amplifiers(), &ring(5)).unwrap();
+
+        assert_eq!(threads[4].output.iter().last(), Some(139629729));
+        for thread in threads {
+            assert_eq!(thread.join(), Ok(MachineStatus::Halt));
+        }
+    }
+
+    #[test]
+    fn test_feedback_loop_in_scheduler() {
+        let mut scheduler = Scheduler::new(amplifiers(), &ring(5)).unwrap();
+        scheduler.run().unwrap();
+
+        assert_eq!(scheduler.output(4).last(), Some(&139629729));
+        assert_eq!(scheduler.output(0).len(), 5);
+        assert!((0..5).all(|index| scheduler.machine(index).halted()));
+    }
+
+    #[test]
+    fn test_scheduler_leaves_stopped_machines_alone() {
+        // Outputs 1 to a machine that spins forever once it has read it.
+        let spinner = IntcodeMachine::new(vec![3, 5, 1105, 1, 2, 0]).with_loop_detection();
+        let mut scheduler = Scheduler::new(vec![IntcodeMachine::new(vec![104, 1, 99]), spinner], &[(0, 1)]).unwrap();
+        scheduler.run().unwrap();
+
+        assert_eq!(scheduler.machine(1).status(), MachineStatus::Loop);
+        assert!(scheduler.machine(1).stopped());
+    }
+
+    #[test]
+    fn test_invalid_links() {
+        assert_eq!(spawn_network(amplifiers(), &[(0, 5)]).err(), Some(NetworkError::InvalidLink(0, 5)));
+        assert_eq!(spawn_network(amplifiers(), &[(0, 1), (2, 2)]).err(), Some(NetworkError::SelfLink(2)));
+        assert_eq!(Scheduler::new(amplifiers(), &[(7, 0)]).err(), Some(NetworkError::InvalidLink(7, 0)));
+
+        // Feeding itself is fine in turns: outputs 1, then reads it back and outputs it again.
+        let machine = IntcodeMachine::new(vec![104, 1, 3, 9, 4, 9, 99, 0, 0, 0]);
+        let mut scheduler = Scheduler::new(vec![machine], &ring(1)).unwrap();
+        scheduler.run().unwrap();
+        assert_eq!(scheduler.output(0), &[1, 1]);
+    }
+}
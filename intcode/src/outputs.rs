@@ -0,0 +1,104 @@
+use crate::{DenseMemory, Input, IntcodeMachine, Memory};
+
+/// Iterator over the values output by a machine, running it lazily until each one.
+/// Ends when the machine halts, waits for input or stops at a breakpoint or watchpoint.
+pub struct Outputs<'a, I, M = DenseMemory> {
+    machine: &'a mut IntcodeMachine<I, Vec<i64>, M>,
+}
+
+/// Iterator over groups of `N` consecutive output values. A trailing incomplete group is dropped.
+pub struct Tuples<'a, I, const N: usize, M = DenseMemory> {
+    outputs: Outputs<'a, I, M>,
+}
+
+impl<I: Input, M: Memory> IntcodeMachine<I, Vec<i64>, M> {
+    /// Iterates over the values output from now on. The machine can be run again after stopping early.
+    /// Panics if the program is malformed, see `try_next_output` for a non-panicking alternative.
+    pub fn outputs(&mut self) -> Outputs<'_, I, M> {
+        Outputs { machine: self }
+    }
+}
+
+impl<'a, I: Input, M: Memory> Outputs<'a, I, M> {
+    /// Groups the output values in arrays of `N`, for instance `(x, y, tile)` triples with `tuples::<3>()`.
+    pub fn tuples<const N: usize>(self) -> Tuples<'a, I, N, M> {
+        Tuples { outputs: self }
+    }
+}
+
+impl<'a, I: Input, M: Memory> Iterator for Outputs<'a, I, M> {
+    type Item = i64;
+
+    fn next(&mut self) -> Option<i64> {
+        self.machine.try_next_output().unwrap_or_else(|error| panic!("{}", error))
+    }
+}
+
+impl<'a, I: Input, const N: usize, M: Memory> Iterator for Tuples<'a, I, N, M> {
+    type Item = [i64; N];
+
+    fn next(&mut self) -> Option<[i64; N]> {
+        let mut tuple = [0; N];
+        for value in tuple.iter_mut() {
+            *value = self.outputs.next()?;
+        }
+        Some(tuple)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{assemble, IntcodeMachine};
+
+    fn squares_program() -> Vec<i64> {
+        // Outputs n and n * n for n counting down from the input.
+        assemble("
+            st [n]
+        loop:
+            ld [n]
+            mul [n], [n], [square]
+            ld [square]
+            add [n], #-1, [n]
+            jnz [n], #loop
+            halt
+        n: .data 0
+        square: .data 0
+        ").unwrap()
+    }
+
+    #[test]
+    fn test_outputs() {
+        let mut machine = IntcodeMachine::new(squares_program()).with_input(3);
+
+        assert_eq!(machine.outputs().collect::<Vec<_>>(), vec![3, 9, 2, 4, 1, 1]);
+        assert!(machine.halted());
+        assert_eq!(machine.outputs().next(), None);
+    }
+
+    #[test]
+    fn test_tuples() {
+        let mut machine = IntcodeMachine::new(squares_program()).with_input(3);
+
+        let pairs: Vec<[i64; 2]> = machine.outputs().tuples::<2>().collect();
+        assert_eq!(pairs, vec![[3, 9], [2, 4], [1, 1]]);
+    }
+
+    #[test]
+    fn test_stop_early_and_resume() {
+        let mut machine = IntcodeMachine::new(squares_program()).with_input(3);
+
+        assert_eq!(machine.outputs().tuples::<2>().next(), Some([3, 9]));
+        assert!(!machine.halted());
+        assert_eq!(machine.run(), vec![2, 4, 1, 1]);
+    }
+
+    #[test]
+    fn test_outputs_stop_for_input() {
+        let mut machine = IntcodeMachine::new(squares_program());
+
+        assert_eq!(machine.outputs().next(), None);
+        assert!(machine.yielded());
+        machine.add_input(1);
+        assert_eq!(machine.outputs().collect::<Vec<_>>(), vec![1, 1]);
+    }
+}
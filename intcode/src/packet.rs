@@ -0,0 +1,230 @@
+use crate::{IntcodeError, IntcodeMachine};
+
+/// A message of two values sent by a machine to the machine at address `dest`.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct Packet {
+    pub dest: i64,
+    pub x: i64,
+    pub y: i64,
+}
+
+/// What to do with a packet.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Route {
+    /// Queue the packet on the machine at its destination. Packets to addresses without a machine are lost.
+    Deliver(Packet),
+    Drop,
+    /// Stop the network, returning the packet from `PacketNetwork::run`.
+    Stop(Packet),
+}
+
+/// Hooks into the traffic of a `PacketNetwork`.
+pub trait Router {
+    /// Called for every packet sent by a machine, before it is delivered.
+    fn route(&mut self, packet: Packet) -> Route {
+        Route::Deliver(packet)
+    }
+
+    /// Called when no machine has anything to read or send. Dropping leaves nothing else to do and stops the network.
+    fn idle(&mut self) -> Route {
+        Route::Drop
+    }
+}
+
+/// Delivers every packet and stops once the network goes idle.
+impl Router for () {}
+
+/// Intercepts packets sent to its address, keeping only the last one, and sends it to address 0
+/// whenever the network goes idle to wake it up. Stops the network when it is about to send
+/// the same `y` twice in a row, returning that packet.
+#[derive(Debug, Clone)]
+pub struct Nat {
+    address: i64,
+    last: Option<Packet>,
+    last_sent: Option<Packet>,
+}
+
+impl Nat {
+    pub fn new(address: i64) -> Nat {
+        Nat { address, last: None, last_sent: None }
+    }
+
+    /// The last packet received, if any.
+    pub fn last(&self) -> Option<Packet> {
+        self.last
+    }
+}
+
+impl Router for Nat {
+    fn route(&mut self, packet: Packet) -> Route {
+        if packet.dest == self.address {
+            self.last = Some(packet);
+            Route::Drop
+        } else {
+            Route::Deliver(packet)
+        }
+    }
+
+    fn idle(&mut self) -> Route {
+        let packet = match self.last {
+            Some(last) => Packet { dest: 0, ..last },
+            None => return Route::Drop,
+        };
+
+        if self.last_sent.map(|sent| sent.y) == Some(packet.y) {
+            Route::Stop(packet)
+        } else {
+            self.last_sent = Some(packet);
+            Route::Deliver(packet)
+        }
+    }
+}
+
+/// Machines running copies of the same program, exchanging packets sent as `dest, x, y` output triples.
+/// Each machine is booted with its address as first input, and reads -1 whenever it has no packet waiting.
+pub struct PacketNetwork {
+    machines: Vec<IntcodeMachine>,
+    /// Output of each machine not yet forming a whole packet.
+    pending: Vec<Vec<i64>>,
+}
+
+impl PacketNetwork {
+    /// Boots `size` machines at addresses 0 to `size - 1`.
+    pub fn new(program: &[i64], size: usize) -> PacketNetwork {
+        PacketNetwork {
+            machines: (0..size).map(|address| IntcodeMachine::new(program.to_vec()).with_input(address as i64)).collect(),
+            pending: vec![vec![]; size],
+        }
+    }
+
+    pub fn machine(&self, address: usize) -> &IntcodeMachine {
+        &self.machines[address]
+    }
+
+    /// Queues a packet on the machine at its destination, if there is one.
+    pub fn send(&mut self, packet: Packet) {
+        if packet.dest >= 0 {
+            if let Some(machine) = self.machines.get_mut(packet.dest as usize) {
+                machine.add_input(packet.x);
+                machine.add_input(packet.y);
+            }
+        }
+    }
+
+    /// Runs the machines in turns, passing every packet they send through `router`.
+    /// Returns the packet the router stopped on, or `None` if every machine stopped
+    /// or the network went idle and the router had nothing to wake it up with.
+    pub fn run<R: Router>(&mut self, router: &mut R) -> Result<Option<Packet>, IntcodeError> {
+        loop {
+            if self.machines.iter().all(|machine| machine.stopped()) {
+                return Ok(None);
+            }
+
+            let mut active = false;
+            for address in 0..self.machines.len() {
+                let machine = &mut self.machines[address];
+                if machine.stopped() {
+                    continue;
+                }
+                if machine.pending_input().next().is_some() {
+                    active = true;
+                } else {
+                    machine.add_input(-1);
+                }
+
+                let output = machine.try_run()?;
+                self.pending[address].extend(output);
+
+                while self.pending[address].len() >= 3 {
+                    let values: Vec<i64> = self.pending[address].drain(..3).collect();
+                    let packet = Packet { dest: values[0], x: values[1], y: values[2] };
+                    active = true;
+
+                    match router.route(packet) {
+                        Route::Deliver(packet) => self.send(packet),
+                        Route::Drop => {}
+                        Route::Stop(packet) => return Ok(Some(packet)),
+                    }
+                }
+            }
+
+            if !active {
+                match router.idle() {
+                    Route::Deliver(packet) => self.send(packet),
+                    Route::Drop => return Ok(None),
+                    Route::Stop(packet) => return Ok(Some(packet)),
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Nat, Packet, PacketNetwork, Route, Router};
+    use crate::assemble;
+
+    fn relay_program() -> Vec<i64> {
+        // Machine 0 sends a packet to machine 1. Every machine passes the packets it receives on
+        // to the next one with x incremented, the last one sending them to address 255.
+        assemble("
+            st [address]
+            jnz [address], #receive
+            ld #1
+            ld #0
+            ld #0
+        receive:
+            st [x]
+            teq [x], #-1, [test]
+            jnz [test], #receive
+            st [y]
+            add [address], #1, [dest]
+            teq [dest], #4, [test]
+            jz [test], #send
+            add #255, #0, [dest]
+        send:
+            ld [dest]
+            add [x], #1, [x]
+            ld [x]
+            ld [y]
+            jz #0, #receive
+        address: .data 0
+        x: .data 0
+        y: .data 0
+        dest: .data 0
+        test: .data 0
+        ").unwrap()
+    }
+
+    struct FirstTo255;
+
+    impl Router for FirstTo255 {
+        fn route(&mut self, packet: Packet) -> Route {
+            if packet.dest == 255 { Route::Stop(packet) } else { Route::Deliver(packet) }
+        }
+    }
+
+    #[test]
+    fn test_intercept_packet() {
+        let mut network = PacketNetwork::new(&relay_program(), 4);
+
+        assert_eq!(network.run(&mut FirstTo255).unwrap(), Some(Packet { dest: 255, x: 3, y: 0 }));
+    }
+
+    #[test]
+    fn test_network_stops_when_idle() {
+        let mut network = PacketNetwork::new(&relay_program(), 4);
+
+        assert_eq!(network.run(&mut ()).unwrap(), None);
+        assert!(network.machine(3).yielded());
+    }
+
+    #[test]
+    fn test_nat_wakes_idle_network() {
+        let mut network = PacketNetwork::new(&relay_program(), 4);
+        let mut nat = Nat::new(255);
+
+        assert_eq!(network.run(&mut nat).unwrap(), Some(Packet { dest: 0, x: 7, y: 0 }));
+        assert_eq!(nat.last(), Some(Packet { dest: 255, x: 7, y: 0 }));
+    }
+}
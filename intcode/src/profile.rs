@@ -0,0 +1,196 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::mem;
+
+use crate::instruction::Opcode;
+use crate::{Input, IntcodeMachine, Memory, Output};
+
+/// How many entries of each table `Profile`'s report shows.
+const REPORT_ROWS: usize = 10;
+
+/// Execution statistics collected while profiling. Its `Display` prints a report of them.
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct Profile {
+    /// Total number of instructions executed.
+    pub instructions: u64,
+    pub opcodes: HashMap<Opcode, u64>,
+    /// Number of times the instruction at each address was executed.
+    pub addresses: BTreeMap<usize, u64>,
+    /// Number of reads of each cell by instruction parameters, immediate ones included.
+    pub reads: BTreeMap<usize, u64>,
+    pub writes: BTreeMap<usize, u64>,
+    /// Largest address read, written or executed.
+    pub max_address: usize,
+    /// Number of times the tape had to grow.
+    pub resizes: u64,
+    /// Number of cells added to the tape by growing it.
+    pub growth: usize,
+}
+
+impl<I: Input, O: Output, M: Memory> IntcodeMachine<I, O, M> {
+    /// Collects execution statistics from now on, by `run` as well as `step`.
+    pub fn start_profiling(&mut self) {
+        self.profiling = true;
+    }
+
+    pub fn stop_profiling(&mut self) {
+        self.profiling = false;
+    }
+
+    /// Statistics collected since profiling started or the profile was last taken.
+    pub fn profile(&self) -> &Profile {
+        &self.profile
+    }
+
+    pub fn take_profile(&mut self) -> Profile {
+        mem::take(&mut self.profile)
+    }
+}
+
+impl Profile {
+    /// Counts an instruction executed as `opcode`, the definition the machine dispatched it to.
+    pub(crate) fn record_instruction(&mut self, address: usize, opcode: Option<Opcode>) {
+        self.instructions += 1;
+        if let Some(opcode) = opcode {
+            *self.opcodes.entry(opcode).or_insert(0) += 1;
+        }
+        *self.addresses.entry(address).or_insert(0) += 1;
+        self.max_address = self.max_address.max(address);
+    }
+
+    pub(crate) fn record_read(&mut self, address: usize) {
+        *self.reads.entry(address).or_insert(0) += 1;
+        self.max_address = self.max_address.max(address);
+    }
+
+    pub(crate) fn record_write(&mut self, address: usize) {
+        *self.writes.entry(address).or_insert(0) += 1;
+        self.max_address = self.max_address.max(address);
+    }
+
+    pub(crate) fn record_growth(&mut self, cells: usize) {
+        self.resizes += 1;
+        self.growth += cells;
+    }
+
+    /// The `count` most executed instruction addresses, most executed first.
+    pub fn hot_spots(&self, count: usize) -> Vec<(usize, u64)> {
+        top(&self.addresses, count)
+    }
+}
+
+/// The `count` entries with the largest values, ties broken by address.
+fn top(counts: &BTreeMap<usize, u64>, count: usize) -> Vec<(usize, u64)> {
+    let mut entries: Vec<(usize, u64)> = counts.iter().map(|(&address, &n)| (address, n)).collect();
+    entries.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+    entries.truncate(count);
+    entries
+}
+
+fn percent(part: u64, total: u64) -> f64 {
+    if total == 0 { 0.0 } else { part as f64 * 100.0 / total as f64 }
+}
+
+fn write_table(f: &mut fmt::Formatter, title: &str, counts: &BTreeMap<usize, u64>) -> fmt::Result {
+    writeln!(f, "{} ({} cells):", title, counts.len())?;
+    for (address, count) in top(counts, REPORT_ROWS) {
+        writeln!(f, "  {:>8} {:>12}", address, count)?;
+    }
+    Ok(())
+}
+
+impl fmt::Display for Profile {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Instructions executed: {}", self.instructions)?;
+
+        writeln!(f, "Opcodes:")?;
+        let mut opcodes: Vec<(&Opcode, &u64)> = self.opcodes.iter().collect();
+        opcodes.sort_by_key(|(opcode, _)| (opcode.code(), opcode.mnemonic()));
+        for (opcode, &count) in opcodes {
+            writeln!(f, "  {:<8} {:>12} {:>6.1}%", opcode.mnemonic(), count, percent(count, self.instructions))?;
+        }
+
+        writeln!(f, "Hot spots:")?;
+        for (address, count) in self.hot_spots(REPORT_ROWS) {
+            writeln!(f, "  {:>8} {:>12} {:>6.1}%", address, count, percent(count, self.instructions))?;
+        }
+
+        write_table(f, "Most read", &self.reads)?;
+        write_table(f, "Most written", &self.writes)?;
+        writeln!(f, "Largest address touched: {}", self.max_address)?;
+        writeln!(f, "Tape growth: {} cells in {} resizes", self.growth, self.resizes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::fixtures::countdown_program;
+    use crate::{CustomInstruction, Effect, IntcodeMachine, Opcode, OpcodeTable};
+
+    #[test]
+    fn test_profile_counts() {
+        let mut machine = IntcodeMachine::new(countdown_program()).with_input(5);
+        machine.start_profiling();
+        machine.run();
+
+        let profile = machine.take_profile();
+        assert_eq!(profile.instructions, 19);
+        assert_eq!(profile.opcodes[&Opcode::Add], 6);
+        assert_eq!(profile.opcodes[&Opcode::Jnz], 5);
+        assert_eq!(profile.opcodes[&Opcode::Halt], 1);
+        assert_eq!(profile.hot_spots(2), vec![(4, 5), (6, 5)]);
+        assert_eq!(profile.writes[&18], 6);
+        assert_eq!(profile.reads[&18], 15);
+        assert_eq!(profile.max_address, 1000);
+        assert_eq!((profile.resizes, profile.growth), (1, 1981));
+        assert_eq!(machine.profile().instructions, 0);
+    }
+
+    #[test]
+    fn test_waiting_for_input_is_counted_once() {
+        let mut machine = IntcodeMachine::new(countdown_program());
+        machine.start_profiling();
+        machine.run();
+        machine.add_input(1);
+        machine.run();
+
+        assert_eq!(machine.profile().addresses[&0], 1);
+        assert_eq!(machine.profile().instructions, 7);
+    }
+
+    #[test]
+    fn test_custom_opcodes_are_counted() {
+        const SUB: CustomInstruction = CustomInstruction {
+            mnemonic: "sub",
+            arity: 3,
+            writes: true,
+            modes: &[],
+            execute: |args| args[0].checked_sub(args[1]).map_or(Effect::Fail, Effect::Store),
+        };
+        const NOP: CustomInstruction = CustomInstruction { mnemonic: "nop", arity: 0, writes: false, modes: &[], execute: |_| Effect::Next };
+        // Opcode 1 subtracts instead of adding.
+        let table = OpcodeTable::new().with_instruction(1, SUB).unwrap().with_instruction(50, NOP).unwrap();
+        let mut machine = IntcodeMachine::new(vec![1101, 9, 4, 0, 50, 50, 4, 0, 99]).with_instruction_set(table);
+        machine.start_profiling();
+        assert_eq!(machine.run(), vec![5]);
+
+        let profile = machine.take_profile();
+        let sub = Opcode::Custom { code: 1, mnemonic: "sub", arity: 3, writes: true };
+        assert_eq!(profile.opcodes[&sub], 1);
+        assert_eq!(profile.opcodes[&Opcode::Custom { code: 50, mnemonic: "nop", arity: 0, writes: false }], 2);
+        assert!(!profile.opcodes.contains_key(&Opcode::Add));
+        assert!(profile.to_string().contains("  sub                 1   20.0%\n  ld                  1   20.0%\n  nop                 2   40.0%\n"));
+    }
+
+    #[test]
+    fn test_report() {
+        let mut machine = IntcodeMachine::new(countdown_program()).with_input(2);
+        machine.start_profiling();
+        machine.run();
+
+        let report = machine.profile().to_string();
+        assert!(report.starts_with("Instructions executed: 10\nOpcodes:\n  add"));
+        assert!(report.contains("  jnz                 2   20.0%\n"));
+        assert!(report.ends_with("Tape growth: 1981 cells in 1 resizes\n"));
+    }
+}
@@ -0,0 +1,423 @@
+use std::collections::vec_deque::VecDeque;
+use std::convert::TryInto;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::Path;
+
+use crate::{Input, IntcodeMachine, MachineStatus, Memory, Segment, Snapshot};
+
+/// Every saved machine starts with these bytes.
+const MAGIC: &[u8; 4] = b"ICM\0";
+
+/// Most cells `load_from` allocates for a saved tape, 1 GiB worth, so a corrupt or crafted
+/// length cannot exhaust memory. Sparse tapes fit when loaded into a `PagedMemory`.
+pub const LOAD_LIMIT: usize = 1 << 27;
+
+/// Version of the layout written by `Snapshot::write_to`.
+///
+/// All integers are little endian:
+/// magic (4 bytes), version (u16), status (u8), position (u64), relative base (i64),
+/// then the tape as a number of segments (u64), each a start address (u64) followed by a list of values,
+/// and the number of cells on the tape (u64),
+/// then the input queue and the output buffer as lists of values,
+/// and finally an FNV-1a checksum (u64) of everything before it.
+/// A list of values is a length (u64) followed by that many i64s.
+///
+/// Version 2 added the `OutOfBudget` (5) and `Loop` (6) statuses, version 3 split the tape in segments
+/// so sparse memories save only the cells they store. Older versions store the whole tape as a single list
+/// and still load.
+pub const SNAPSHOT_VERSION: u16 = 3;
+
+/// Errors raised when reading a saved machine.
+#[derive(Debug)]
+pub enum LoadError {
+    Io(io::Error),
+    /// The data does not start with the snapshot magic bytes.
+    NotASnapshot,
+    UnsupportedVersion(u16),
+    /// The data ends before all the fields announced by the header were read.
+    Truncated,
+    /// A segment of the tape overlaps the previous one or lies past the end of the tape.
+    InvalidTape,
+    InvalidStatus(u8),
+    /// Restoring the tape would allocate more cells than the memory limit allows.
+    MemoryLimit,
+    ChecksumMismatch,
+    /// There are extra bytes after the checksum.
+    TrailingData,
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LoadError::Io(error) => write!(f, "Failed to read snapshot: {}", error),
+            LoadError::NotASnapshot => write!(f, "Not an Intcode snapshot"),
+            LoadError::UnsupportedVersion(version) => {
+                write!(f, "Unsupported snapshot version {}, expected {}", version, SNAPSHOT_VERSION)
+            }
+            LoadError::Truncated => write!(f, "Snapshot is truncated"),
+            LoadError::InvalidTape => write!(f, "Snapshot tape segments are out of place"),
+            LoadError::InvalidStatus(status) => write!(f, "Invalid machine status {} in snapshot", status),
+            LoadError::MemoryLimit => write!(f, "Snapshot tape does not fit in the memory limit"),
+            LoadError::ChecksumMismatch => write!(f, "Snapshot checksum does not match, the file is corrupt"),
+            LoadError::TrailingData => write!(f, "Unexpected data after the end of the snapshot"),
+        }
+    }
+}
+
+impl Error for LoadError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            LoadError::Io(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for LoadError {
+    fn from(error: io::Error) -> Self {
+        LoadError::Io(error)
+    }
+}
+
+pub(crate) fn status_code(status: MachineStatus) -> u8 {
+    match status {
+        MachineStatus::Run => 0,
+        MachineStatus::Yield => 1,
+        MachineStatus::Halt => 2,
+        MachineStatus::Breakpoint => 3,
+        MachineStatus::Watchpoint => 4,
+        MachineStatus::OutOfBudget => 5,
+        MachineStatus::Loop => 6,
+    }
+}
+
+pub(crate) fn status_from_code(code: u8) -> Result<MachineStatus, LoadError> {
+    match code {
+        0 => Ok(MachineStatus::Run),
+        1 => Ok(MachineStatus::Yield),
+        2 => Ok(MachineStatus::Halt),
+        3 => Ok(MachineStatus::Breakpoint),
+        4 => Ok(MachineStatus::Watchpoint),
+        5 => Ok(MachineStatus::OutOfBudget),
+        6 => Ok(MachineStatus::Loop),
+        _ => Err(LoadError::InvalidStatus(code)),
+    }
+}
+
+fn checksum(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &byte| (hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3))
+}
+
+fn put_values<'a>(bytes: &mut Vec<u8>, values: impl ExactSizeIterator<Item = &'a i64>) {
+    bytes.extend_from_slice(&(values.len() as u64).to_le_bytes());
+    values.for_each(|value| bytes.extend_from_slice(&value.to_le_bytes()));
+}
+
+/// Reads fields from the front of a byte slice.
+struct Cursor<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Cursor<'a> {
+    fn take(&mut self, count: usize) -> Result<&'a [u8], LoadError> {
+        if count > self.bytes.len() {
+            return Err(LoadError::Truncated);
+        }
+        let (taken, rest) = self.bytes.split_at(count);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    fn u8(&mut self) -> Result<u8, LoadError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, LoadError> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Result<u64, LoadError> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    fn i64(&mut self) -> Result<i64, LoadError> {
+        Ok(i64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    /// Reads a length prefixed list of values, checking the length against the data left
+    /// so a corrupt length cannot trigger a huge allocation.
+    fn values(&mut self) -> Result<Vec<i64>, LoadError> {
+        let length = self.u64()?;
+        if length > (self.bytes.len() / 8) as u64 {
+            return Err(LoadError::Truncated);
+        }
+        (0..length).map(|_| self.i64()).collect()
+    }
+
+    /// Reads the segments of a tape followed by its length, checking that they are in order and fit in it.
+    fn segments(&mut self) -> Result<(usize, Vec<Segment>), LoadError> {
+        let count = self.u64()?;
+        if count > (self.bytes.len() / 16) as u64 {
+            return Err(LoadError::Truncated);
+        }
+        let segments = (0..count)
+            .map(|_| Ok((self.u64()? as usize, self.values()?)))
+            .collect::<Result<Vec<_>, LoadError>>()?;
+        let length = self.u64()? as usize;
+
+        let mut end = 0;
+        for (start, values) in &segments {
+            if *start < end {
+                return Err(LoadError::InvalidTape);
+            }
+            end = start.checked_add(values.len()).ok_or(LoadError::InvalidTape)?;
+        }
+        if end > length {
+            return Err(LoadError::InvalidTape);
+        }
+        Ok((length, segments))
+    }
+}
+
+impl Snapshot {
+    /// Encodes the snapshot in the versioned binary layout described by `SNAPSHOT_VERSION`.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let cells: usize = self.tape.iter().map(|(_, values)| 2 + values.len()).sum();
+        let mut bytes = Vec::with_capacity(55 + 8 * (cells + self.input.len() + self.output.len()));
+        bytes.extend_from_slice(MAGIC);
+        bytes.extend_from_slice(&SNAPSHOT_VERSION.to_le_bytes());
+        bytes.push(status_code(self.status));
+        bytes.extend_from_slice(&(self.position as u64).to_le_bytes());
+        bytes.extend_from_slice(&(self.relative_base as i64).to_le_bytes());
+        bytes.extend_from_slice(&(self.tape.len() as u64).to_le_bytes());
+        for (start, values) in &self.tape {
+            bytes.extend_from_slice(&(*start as u64).to_le_bytes());
+            put_values(&mut bytes, values.iter());
+        }
+        bytes.extend_from_slice(&(self.length as u64).to_le_bytes());
+        put_values(&mut bytes, self.input.iter());
+        put_values(&mut bytes, self.output.iter());
+
+        let checksum = checksum(&bytes);
+        bytes.extend_from_slice(&checksum.to_le_bytes());
+        bytes
+    }
+
+    /// Decodes a snapshot written by `to_bytes`, rejecting anything that is not a complete, intact snapshot.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Snapshot, LoadError> {
+        let mut cursor = Cursor { bytes };
+
+        if cursor.take(MAGIC.len()).map_err(|_| LoadError::NotASnapshot)? != MAGIC {
+            return Err(LoadError::NotASnapshot);
+        }
+
+        let version = cursor.u16()?;
+        if version == 0 || version > SNAPSHOT_VERSION {
+            return Err(LoadError::UnsupportedVersion(version));
+        }
+
+        let status = cursor.u8()?;
+        let position = cursor.u64()?;
+        let relative_base = cursor.i64()?;
+        let (tape_length, tape) = if version < 3 {
+            let values = cursor.values()?;
+            (values.len(), vec![(0, values)])
+        } else {
+            cursor.segments()?
+        };
+        let input = cursor.values()?;
+        let output = cursor.values()?;
+
+        let length = bytes.len() - cursor.bytes.len();
+        if cursor.u64()? != checksum(&bytes[..length]) {
+            return Err(LoadError::ChecksumMismatch);
+        }
+        if !cursor.bytes.is_empty() {
+            return Err(LoadError::TrailingData);
+        }
+
+        Ok(Snapshot {
+            length: tape_length,
+            tape,
+            position: position as usize,
+            relative_base: relative_base as isize,
+            input: VecDeque::from(input),
+            output,
+            status: status_from_code(status)?,
+        })
+    }
+
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        writer.write_all(&self.to_bytes())
+    }
+
+    pub fn read_from<R: Read>(reader: &mut R) -> Result<Snapshot, LoadError> {
+        let mut bytes = vec![];
+        reader.read_to_end(&mut bytes)?;
+        Snapshot::from_bytes(&bytes)
+    }
+}
+
+impl<M: Memory + Default> IntcodeMachine<(), Vec<i64>, M> {
+    /// Builds a machine in the state captured by `snapshot`, on a memory of type `M`.
+    pub fn from_snapshot(snapshot: &Snapshot) -> Self {
+        let mut machine = IntcodeMachine::from_memory(M::default());
+        machine.restore(snapshot);
+        machine
+    }
+
+    /// Reads a machine saved with `save_to` into a memory of type `M`, refusing tapes that would
+    /// allocate more than `LOAD_LIMIT` cells in it.
+    pub fn load_from<P: AsRef<Path>>(path: P) -> Result<Self, LoadError> {
+        let snapshot = Snapshot::from_bytes(&fs::read(path)?)?;
+        let mut machine = IntcodeMachine::from_memory(M::default());
+        machine.set_memory_limit(Some(LOAD_LIMIT));
+        machine.try_restore(&snapshot)?;
+        machine.set_memory_limit(None);
+        Ok(machine)
+    }
+}
+
+impl<I: Input, M: Memory> IntcodeMachine<I, Vec<i64>, M> {
+    /// Writes the machine state to a file, to be resumed later with `load_from`, or with `restore`
+    /// on a machine built with the same kind of memory.
+    pub fn save_to<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        fs::write(path, self.snapshot().to_bytes())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{checksum, put_values, status_code, LoadError, MAGIC};
+    use crate::{DenseMemory, IntcodeMachine, Memory, MachineStatus, PagedMemory, Snapshot};
+
+    fn paused_machine() -> IntcodeMachine {
+        // Outputs 7, relocates the relative base and then waits for an input to echo.
+        let tape: Vec<i64> = vec![104, 7, 109, -3, 3, 100, 4, 100, 99];
+        let mut machine = IntcodeMachine::new(tape);
+        machine.run();
+        machine
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let machine = paused_machine()
+            .with_input(11)
+            .with_input(12);
+        let snapshot = machine.snapshot();
+
+        assert_eq!(Snapshot::from_bytes(&snapshot.to_bytes()).unwrap(), snapshot);
+    }
+
+    #[test]
+    fn test_saved_machine_resumes() {
+        let path = std::env::temp_dir().join(format!("intcode-save-test-{}", std::process::id()));
+        paused_machine().save_to(&path).unwrap();
+
+        let mut machine: IntcodeMachine = IntcodeMachine::load_from(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(machine.relative_base(), -3);
+        assert_eq!(machine.output(), &[7]);
+        machine.add_input(42);
+        assert_eq!(machine.run(), vec![42]);
+        assert!(machine.halted());
+    }
+
+    #[test]
+    fn test_new_statuses_and_old_versions() {
+        let mut machine = IntcodeMachine::new(vec![1105, 1, 0]).with_loop_detection();
+        machine.run();
+        let bytes = machine.snapshot().to_bytes();
+        assert_eq!(Snapshot::from_bytes(&bytes).unwrap().status, MachineStatus::Loop);
+
+        // Version 1 stores the whole tape as a single list of values.
+        let snapshot = paused_machine().snapshot();
+        let mut old = MAGIC.to_vec();
+        old.extend_from_slice(&1u16.to_le_bytes());
+        old.push(status_code(snapshot.status));
+        old.extend_from_slice(&(snapshot.position as u64).to_le_bytes());
+        old.extend_from_slice(&(snapshot.relative_base as i64).to_le_bytes());
+        put_values(&mut old, snapshot.tape[0].1.iter());
+        put_values(&mut old, snapshot.input.iter());
+        put_values(&mut old, snapshot.output.iter());
+        let checksum = checksum(&old);
+        old.extend_from_slice(&checksum.to_le_bytes());
+        assert_eq!(Snapshot::from_bytes(&old).unwrap(), snapshot);
+    }
+
+    #[test]
+    fn test_paged_machine_saves_only_its_pages() {
+        let tape = vec![1101, 3, 4, 1_000_000_000, 3, 100, 4, 1_000_000_000, 99];
+        let mut machine = IntcodeMachine::from_memory(PagedMemory::new(tape));
+        machine.run();
+        let bytes = machine.snapshot().to_bytes();
+        assert!(bytes.len() < 3 * 8 * 1024);
+
+        let mut restored = IntcodeMachine::from_memory(PagedMemory::default());
+        restored.restore(&Snapshot::from_bytes(&bytes).unwrap());
+        restored.add_input(0);
+        assert_eq!(restored.run(), vec![7]);
+        assert_eq!(restored.memory().len(), 1_000_000_001);
+    }
+
+    #[test]
+    fn test_load_respects_the_memory_kind_and_limit() {
+        let path = std::env::temp_dir().join(format!("intcode-far-save-test-{}", std::process::id()));
+        let tape = vec![1101, 3, 4, 1_000_000_000, 3, 100, 4, 1_000_000_000, 99];
+        let mut machine = IntcodeMachine::from_memory(PagedMemory::new(tape));
+        machine.run();
+        machine.save_to(&path).unwrap();
+
+        let dense = IntcodeMachine::<(), Vec<i64>, DenseMemory>::load_from(&path);
+        let paged = IntcodeMachine::<(), Vec<i64>, PagedMemory>::load_from(&path);
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(matches!(dense, Err(LoadError::MemoryLimit)));
+        let mut paged = paged.unwrap();
+        assert_eq!(paged.memory().allocated(), 2 * 1024);
+        paged.add_input(0);
+        assert_eq!(paged.run(), vec![7]);
+    }
+
+    #[test]
+    fn test_restore_leaves_machine_untouched_past_the_limit() {
+        let mut far = paused_machine().snapshot();
+        far.length = usize::MAX;
+
+        let mut machine = paused_machine().with_memory_limit(1 << 20);
+        assert!(matches!(machine.try_restore(&far), Err(LoadError::MemoryLimit)));
+        assert_eq!(machine.snapshot(), paused_machine().snapshot());
+    }
+
+    #[test]
+    fn test_rejects_corrupt_data() {
+        let bytes = paused_machine().snapshot().to_bytes();
+
+        assert!(matches!(Snapshot::from_bytes(b"1,2,3,99"), Err(LoadError::NotASnapshot)));
+        assert!(matches!(Snapshot::from_bytes(&bytes[..bytes.len() - 3]), Err(LoadError::Truncated)));
+
+        let mut version = bytes.clone();
+        version[4] = 9;
+        assert!(matches!(Snapshot::from_bytes(&version), Err(LoadError::UnsupportedVersion(9))));
+
+        let mut flipped = bytes.clone();
+        flipped[50] ^= 1;
+        assert!(matches!(Snapshot::from_bytes(&flipped), Err(LoadError::ChecksumMismatch)));
+
+        let mut length = bytes.clone();
+        length[23] = 0xff;
+        assert!(matches!(Snapshot::from_bytes(&length), Err(LoadError::Truncated)));
+
+        let mut segment = bytes.clone();
+        segment[38] = 0x7f;
+        assert!(matches!(Snapshot::from_bytes(&segment), Err(LoadError::InvalidTape)));
+
+        let mut trailing = bytes;
+        trailing.push(0);
+        assert!(matches!(Snapshot::from_bytes(&trailing), Err(LoadError::TrailingData)));
+    }
+}
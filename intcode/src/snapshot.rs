@@ -0,0 +1,114 @@
+use std::collections::vec_deque::VecDeque;
+
+use crate::{Input, IntcodeMachine, LoadError, MachineStatus, Memory, Segment};
+
+/// The execution state of a machine at some point in time.
+/// Breakpoints and watchpoints are debugging settings rather than state, so they are not part of it.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Snapshot {
+    /// Number of cells on the tape.
+    pub(crate) length: usize,
+    /// The stretches of the tape the memory stored, by address of their first cell. Every other cell is zero.
+    pub(crate) tape: Vec<Segment>,
+    pub(crate) position: usize,
+    pub(crate) relative_base: isize,
+    pub(crate) input: VecDeque<i64>,
+    pub(crate) output: Vec<i64>,
+    pub(crate) status: MachineStatus,
+}
+
+impl Snapshot {
+    /// A running machine about to execute the instruction at `position`, with nothing output yet.
+    pub fn new(tape: Vec<i64>, position: usize, relative_base: isize, input: VecDeque<i64>) -> Snapshot {
+        Snapshot {
+            length: tape.len(),
+            tape: vec![(0, tape)],
+            position,
+            relative_base,
+            input,
+            output: vec![],
+            status: MachineStatus::Run,
+        }
+    }
+}
+
+impl<I: Input, M: Memory> IntcodeMachine<I, Vec<i64>, M> {
+    /// Captures the current state, so the machine can be rewound to it with `restore`.
+    /// Only the cells the memory stores are copied, so sparse tapes stay small.
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            length: self.tape.len(),
+            tape: self.tape.segments().into_iter().map(|(start, cells)| (start, cells.to_vec())).collect(),
+            position: self.position,
+            relative_base: self.relative_base,
+            input: self.input.clone(),
+            output: self.output.clone(),
+            status: self.status,
+        }
+    }
+
+    /// Puts the machine back in the state captured by `snapshot`, keeping its breakpoints and watchpoints.
+    /// The undo history no longer applies to the restored state and is discarded.
+    ///
+    /// Panics if the tape does not fit in the memory limit, see `try_restore` for a non-panicking version.
+    pub fn restore(&mut self, snapshot: &Snapshot) {
+        self.try_restore(snapshot).unwrap();
+    }
+
+    /// Like `restore`, but leaves the machine untouched and returns `LoadError::MemoryLimit`
+    /// when the tape of the snapshot needs more cells than the memory limit allows.
+    pub fn try_restore(&mut self, snapshot: &Snapshot) -> Result<(), LoadError> {
+        if !self.tape.load(snapshot.length, &snapshot.tape) {
+            return Err(LoadError::MemoryLimit);
+        }
+        self.cache.clear();
+        self.position = snapshot.position;
+        self.relative_base = snapshot.relative_base;
+        self.input = snapshot.input.clone();
+        self.output = snapshot.output.clone();
+        self.status = snapshot.status;
+        self.watch_hits.clear();
+        self.history.clear();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::fixtures::echo_sum_program;
+    use crate::IntcodeMachine;
+
+    #[test]
+    fn test_clone_forks_the_machine() {
+        let mut machine = IntcodeMachine::new(echo_sum_program())
+            .with_input(10);
+        machine.run();
+        assert!(machine.yielded());
+
+        let mut fork = machine.clone();
+        machine.add_input(1);
+        fork.add_input(2);
+
+        assert_eq!(machine.run(), vec![11]);
+        assert_eq!(fork.run(), vec![12]);
+    }
+
+    #[test]
+    fn test_restore_rewinds_to_snapshot() {
+        let mut machine = IntcodeMachine::new(echo_sum_program())
+            .with_input(10);
+        machine.run();
+        let snapshot = machine.snapshot();
+
+        machine.add_input(1);
+        assert_eq!(machine.run(), vec![11]);
+        assert!(machine.halted());
+
+        machine.restore(&snapshot);
+        assert!(machine.yielded());
+        assert_eq!(machine.snapshot(), snapshot);
+
+        machine.add_input(5);
+        assert_eq!(machine.run(), vec![15]);
+    }
+}
@@ -0,0 +1,310 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt;
+use std::ops::RangeInclusive;
+
+use crate::symbolic::{Expr, Linear};
+
+/// Errors raised when building or solving a system of equations.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum SolveError {
+    /// An equation is not linear in the symbols, or its coefficients do not fit in an `i64`.
+    NonLinear(Expr),
+    /// Finding a solution means trying values for a symbol that has no bounds.
+    Unbounded(String),
+}
+
+impl fmt::Display for SolveError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SolveError::NonLinear(expr) => write!(f, "Expression {} is not linear", expr),
+            SolveError::Unbounded(symbol) => write!(f, "Symbol {} needs bounds to be solved for", symbol),
+        }
+    }
+}
+
+impl Error for SolveError {}
+
+/// Finds integer values for symbols satisfying a set of linear equations, each symbol within optional bounds.
+///
+/// Equations with a single unknown are solved directly, and equations with two unknowns with the extended
+/// Euclidean algorithm, which leaves a progression of candidates bounded by the ranges of both.
+/// Only when every equation has more unknowns does the solver try the values of a symbol one by one,
+/// picking the one with the smallest range.
+#[derive(Debug, Clone, Default)]
+pub struct Solver {
+    /// Each equation states that the expression equals zero.
+    equations: Vec<Linear>,
+    bounds: BTreeMap<String, (i64, i64)>,
+}
+
+/// Greatest common divisor `g` of `a` and `b`, along with `x` and `y` such that `a * x + b * y = g`.
+fn extended_gcd(a: i128, b: i128) -> (i128, i128, i128) {
+    if b == 0 {
+        (a.abs(), a.signum(), 0)
+    } else {
+        let (g, x, y) = extended_gcd(b, a % b);
+        (g, y, x - (a / b) * y)
+    }
+}
+
+fn div_floor(a: i128, b: i128) -> i128 {
+    let quotient = a / b;
+    if a % b != 0 && (a < 0) != (b < 0) { quotient - 1 } else { quotient }
+}
+
+fn div_ceil(a: i128, b: i128) -> i128 {
+    -div_floor(-a, b)
+}
+
+impl Solver {
+    pub fn new() -> Solver {
+        Solver::default()
+    }
+
+    /// Restricts the values `symbol` can take.
+    pub fn bound(&mut self, symbol: &str, range: RangeInclusive<i64>) {
+        self.bounds.insert(symbol.to_string(), (*range.start(), *range.end()));
+    }
+
+    pub fn with_bound(mut self, symbol: &str, range: RangeInclusive<i64>) -> Self {
+        self.bound(symbol, range);
+        self
+    }
+
+    /// Adds the equation `left == right`.
+    pub fn equal(&mut self, left: &Expr, right: &Expr) -> Result<(), SolveError> {
+        let left = left.as_linear().ok_or_else(|| SolveError::NonLinear(left.clone()))?;
+        let right = right.as_linear().ok_or_else(|| SolveError::NonLinear(right.clone()))?;
+        let difference = right.checked_scale(-1).and_then(|negated| left.checked_add(&negated));
+        let difference = difference.ok_or_else(|| {
+            SolveError::NonLinear(Expr::Linear(left.clone()) + Expr::Linear(right.clone()) * Expr::constant(-1))
+        })?;
+        self.equations.push(difference);
+        Ok(())
+    }
+
+    /// Finds values for every symbol in the equations and bounds, or `None` if there are none.
+    pub fn solve(&self) -> Result<Option<BTreeMap<String, i64>>, SolveError> {
+        let mut values = BTreeMap::new();
+        Ok(if self.search(&mut values)? { Some(values) } else { None })
+    }
+
+    fn bounds(&self, symbol: &str) -> (i128, i128) {
+        self.bounds.get(symbol).map_or((i64::MIN as i128, i64::MAX as i128), |&(low, high)| (low as i128, high as i128))
+    }
+
+    fn in_bounds(&self, symbol: &str, value: i128) -> bool {
+        let (low, high) = self.bounds(symbol);
+        low <= value && value <= high
+    }
+
+    /// Tries `candidates` for the symbols in turn, keeping the first that lets the remaining equations be solved.
+    fn attempt(&self, values: &mut BTreeMap<String, i64>, candidates: &[(&str, i128)]) -> Result<bool, SolveError> {
+        if candidates.iter().any(|&(symbol, value)| !self.in_bounds(symbol, value)) {
+            return Ok(false);
+        }
+        for &(symbol, value) in candidates {
+            values.insert(symbol.to_string(), value as i64);
+        }
+        if self.search(values)? {
+            return Ok(true);
+        }
+        for &(symbol, _) in candidates {
+            values.remove(symbol);
+        }
+        Ok(false)
+    }
+
+    fn search(&self, values: &mut BTreeMap<String, i64>) -> Result<bool, SolveError> {
+        let mut remaining = vec![];
+        for equation in &self.equations {
+            let equation = match equation.substitute(values) {
+                Some(equation) => equation,
+                // With every symbol known, a sum too large for an i64 is not zero.
+                None if equation.terms.keys().all(|symbol| values.contains_key(symbol)) => return Ok(false),
+                // Otherwise the values tried are so large that the remaining symbols have no useful bounds.
+                None => {
+                    let symbol = equation.terms.keys().find(|symbol| !values.contains_key(*symbol)).unwrap();
+                    return Err(SolveError::Unbounded(symbol.clone()));
+                }
+            };
+            match equation.terms.len() {
+                0 if equation.constant != 0 => return Ok(false),
+                0 => {}
+                _ => remaining.push(equation),
+            }
+        }
+
+        let equation = match remaining.iter().min_by_key(|equation| equation.terms.len()) {
+            Some(equation) => equation,
+            None => {
+                // Symbols only constrained by their bounds take their lowest value.
+                for (symbol, &(low, high)) in &self.bounds {
+                    if low > high {
+                        return Ok(false);
+                    }
+                    values.entry(symbol.clone()).or_insert(low);
+                }
+                return Ok(true);
+            }
+        };
+
+        let terms: Vec<(&str, i128)> = equation.terms.iter().map(|(symbol, &a)| (symbol.as_str(), a as i128)).collect();
+        let constant = equation.constant as i128;
+        match terms[..] {
+            [(x, a)] => {
+                if constant % a != 0 {
+                    return Ok(false);
+                }
+                self.attempt(values, &[(x, -constant / a)])
+            }
+            [(x, a), (y, b)] => {
+                // a * x + b * y = c has solutions x0 + (b / g) * t, y0 - (a / g) * t when g divides c.
+                let (g, u, v) = extended_gcd(a, b);
+                let c = -constant;
+                if c % g != 0 {
+                    return Ok(false);
+                }
+                let unbounded = || SolveError::Unbounded(x.to_string());
+                let (x0, y0) = match (u.checked_mul(c / g), v.checked_mul(c / g)) {
+                    (Some(x0), Some(y0)) => (x0, y0),
+                    _ => return Err(unbounded()),
+                };
+                let (dx, dy) = (b / g, -a / g);
+
+                let mut low = i128::MIN;
+                let mut high = i128::MAX;
+                for &(symbol, start, step) in &[(x, x0, dx), (y, y0, dy)] {
+                    let (min, max) = self.bounds(symbol);
+                    let (from, to) = match (min.checked_sub(start), max.checked_sub(start)) {
+                        (Some(min), Some(max)) if step > 0 => (div_ceil(min, step), div_floor(max, step)),
+                        (Some(min), Some(max)) => (div_ceil(max, step), div_floor(min, step)),
+                        _ => return Err(unbounded()),
+                    };
+                    low = low.max(from);
+                    high = high.min(to);
+                }
+                if high.checked_sub(low).is_none_or(|span| span > u32::MAX as i128) {
+                    return Err(unbounded());
+                }
+
+                let mut t = low;
+                while t <= high {
+                    let step = |start: i128, step: i128| step.checked_mul(t).and_then(|offset| start.checked_add(offset));
+                    let (x_value, y_value) = match (step(x0, dx), step(y0, dy)) {
+                        (Some(x_value), Some(y_value)) => (x_value, y_value),
+                        _ => return Err(unbounded()),
+                    };
+                    if self.attempt(values, &[(x, x_value), (y, y_value)])? {
+                        return Ok(true);
+                    }
+                    t += 1;
+                }
+                Ok(false)
+            }
+            _ => {
+                let (symbol, _) = terms.iter().min_by_key(|(symbol, _)| {
+                    let (low, high) = self.bounds(symbol);
+                    high - low
+                }).unwrap();
+                let (low, high) = self.bounds(symbol);
+                if high - low > u32::MAX as i128 {
+                    return Err(SolveError::Unbounded(symbol.to_string()));
+                }
+
+                for value in low..=high {
+                    if self.attempt(values, &[(symbol, value)])? {
+                        return Ok(true);
+                    }
+                }
+                Ok(false)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use super::{SolveError, Solver};
+    use crate::{Expr, IntcodeMachine, SymbolicMachine};
+
+    fn values(pairs: &[(&str, i64)]) -> BTreeMap<String, i64> {
+        pairs.iter().map(|&(symbol, value)| (symbol.to_string(), value)).collect()
+    }
+
+    #[test]
+    fn test_solve_for_noun_and_verb() {
+        // Address 0 ends up as `1000 * noun + verb + 7`.
+        let tape = vec![1, 0, 0, 3, 2, 1, 17, 18, 1, 18, 2, 0, 1, 0, 19, 0, 99, 1000, 0, 7];
+        let result = SymbolicMachine::new(tape.clone())
+            .with_init(Expr::symbol("noun"), Expr::symbol("verb"))
+            .run_for_target(0)
+            .unwrap();
+
+        let mut solver = Solver::new().with_bound("noun", 0..=99).with_bound("verb", 0..=99);
+        solver.equal(&result, &Expr::constant(42057)).unwrap();
+        let solution = solver.solve().unwrap().unwrap();
+
+        assert_eq!(solution, values(&[("noun", 42), ("verb", 50)]));
+        assert_eq!(IntcodeMachine::new(tape).with_init(42, 50).run_for_target(0), 42057);
+    }
+
+    #[test]
+    fn test_single_unknown() {
+        let mut solver = Solver::new();
+        solver.equal(&(Expr::symbol("x") * Expr::constant(3)), &Expr::constant(-12)).unwrap();
+        assert_eq!(solver.solve(), Ok(Some(values(&[("x", -4)]))));
+
+        let mut solver = Solver::new();
+        solver.equal(&(Expr::symbol("x") * Expr::constant(3)), &Expr::constant(7)).unwrap();
+        assert_eq!(solver.solve(), Ok(None));
+    }
+
+    #[test]
+    fn test_system_of_equations() {
+        // x + y + z = 9, x = y + 1, 2z = y + 1 within 0..=10.
+        let (x, y, z) = (Expr::symbol("x"), Expr::symbol("y"), Expr::symbol("z"));
+        let mut solver = Solver::new().with_bound("x", 0..=10).with_bound("y", 0..=10).with_bound("z", 0..=10);
+        solver.equal(&(x.clone() + y.clone() + z.clone()), &Expr::constant(9)).unwrap();
+        solver.equal(&x, &(y.clone() + Expr::constant(1))).unwrap();
+        solver.equal(&(z * Expr::constant(2)), &(y + Expr::constant(1))).unwrap();
+
+        assert_eq!(solver.solve(), Ok(Some(values(&[("x", 4), ("y", 3), ("z", 2)]))));
+    }
+
+    #[test]
+    fn test_no_solution_within_bounds() {
+        let mut solver = Solver::new().with_bound("a", 0..=9).with_bound("b", 0..=9);
+        solver.equal(&(Expr::symbol("a") * Expr::constant(10) + Expr::symbol("b")), &Expr::constant(100)).unwrap();
+
+        assert_eq!(solver.solve(), Ok(None));
+    }
+
+    #[test]
+    fn test_errors() {
+        let mut solver = Solver::new();
+        let product = Expr::symbol("x") * Expr::symbol("y");
+        assert_eq!(solver.equal(&product, &Expr::constant(6)), Err(SolveError::NonLinear(product)));
+
+        solver.equal(&(Expr::symbol("x") + Expr::symbol("y") + Expr::symbol("z")), &Expr::constant(6)).unwrap();
+        assert!(matches!(solver.solve(), Err(SolveError::Unbounded(_))));
+    }
+
+    #[test]
+    fn test_extreme_coefficients() {
+        // The particular solution is around 2^126, far outside the bounds of either symbol.
+        let mut solver = Solver::new();
+        let sum = Expr::symbol("x") * Expr::constant(i64::MAX) + Expr::symbol("y") * Expr::constant(i64::MAX - 1);
+        solver.equal(&sum, &Expr::constant(i64::MAX)).unwrap();
+        let solution = solver.solve().unwrap().unwrap();
+        let (x, y) = (solution["x"] as i128, solution["y"] as i128);
+        assert_eq!(x * i64::MAX as i128 + y * (i64::MAX - 1) as i128, i64::MAX as i128);
+
+        let mut solver = Solver::new();
+        let big = Expr::symbol("x") * Expr::constant(i64::MIN);
+        assert!(matches!(solver.equal(&Expr::constant(0), &big), Err(SolveError::NonLinear(_))));
+    }
+}
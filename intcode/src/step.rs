@@ -0,0 +1,203 @@
+use crate::instruction::{Instruction, Opcode, Operand, ParameterMode};
+use crate::{Input, IntcodeError, IntcodeMachine, MachineStatus, Memory, Output};
+
+/// A tape cell read by an instruction parameter. Immediate parameters read their own cell.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct MemoryRead {
+    pub address: usize,
+    pub value: i64,
+}
+
+/// A tape cell overwritten by an instruction.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct MemoryWrite {
+    pub address: usize,
+    pub old: i64,
+    pub new: i64,
+}
+
+/// Everything a single executed instruction did.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Step {
+    /// The instruction as it was on the tape before it executed.
+    pub instruction: Instruction,
+    pub reads: Vec<MemoryRead>,
+    pub writes: Vec<MemoryWrite>,
+    /// Old and new relative base, when changed by `rel`.
+    pub relative_base: Option<(isize, isize)>,
+    /// Input value consumed by `st`.
+    pub input: Option<i64>,
+    /// Value emitted by `ld`.
+    pub output: Option<i64>,
+    /// Status of the machine after the instruction.
+    pub status: MachineStatus,
+}
+
+impl<I: Input, O: Output, M: Memory> IntcodeMachine<I, O, M> {
+    /// Decodes the instruction under the instruction pointer the same way `execute` would, with the instruction set
+    /// if there is one: cells past the end of the tape read as zeroes and invalid opcodes or modes are errors.
+    pub(crate) fn decode(&self) -> Result<Instruction, IntcodeError> {
+        let instruction = self.instruction();
+        let code = instruction % 100;
+        let opcode = match &self.instruction_set {
+            Some(set) => set.definition(code).map(|definition| definition.opcode(code)),
+            None => Opcode::from_code(code),
+        };
+        let opcode = opcode.ok_or(IntcodeError::UnknownOpcode { position: self.position, instruction })?;
+
+        let mut modes = instruction / 100;
+        let mut operands = Vec::with_capacity(opcode.arity());
+        for offset in 1..=opcode.arity() {
+            let mode = self.parse_mode(modes % 10)?;
+            if self.instruction_set.as_ref().is_some_and(|set| !set.allows_mode(code, offset - 1, mode)) {
+                return Err(IntcodeError::InvalidParameterMode { position: self.position, instruction, mode: modes % 10 });
+            }
+            let value = self.tape.get(self.position + offset);
+            operands.push(Operand { mode, value });
+            modes /= 10;
+        }
+
+        if opcode.writes() && operands.last().map(|operand| operand.mode) == Some(ParameterMode::Immediate) {
+            return Err(IntcodeError::ImmediateWrite { position: self.position, instruction });
+        }
+
+        Ok(Instruction { address: self.position, opcode, operands })
+    }
+
+    /// Executes exactly one instruction, ignoring breakpoints, and reports what it did.
+    /// Stepping a `st` without queued input leaves the machine in place with a `Yield` status,
+    /// touching a watched cell sets a `Watchpoint` status.
+    pub fn step(&mut self) -> Result<Step, IntcodeError> {
+        self.status = MachineStatus::Run;
+        self.watch_hits.clear();
+
+        self.execute_logged()
+    }
+
+    /// Executes an instruction and keeps its record in the trace and the undo history, if they are enabled.
+    pub(crate) fn execute_logged(&mut self) -> Result<Step, IntcodeError> {
+        let tape_length = self.tape.len();
+        let step = self.execute_recorded()?;
+
+        self.record_trace(&step);
+        if self.history_limit > 0 {
+            self.remember(step.clone(), tape_length);
+        }
+        Ok(step)
+    }
+
+    /// Executes the instruction under the instruction pointer, recording what it did.
+    pub(crate) fn execute_recorded(&mut self) -> Result<Step, IntcodeError> {
+        self.current = Some(Step {
+            instruction: self.decode()?,
+            reads: vec![],
+            writes: vec![],
+            relative_base: None,
+            input: None,
+            output: None,
+            status: MachineStatus::Run,
+        });
+
+        let result = self.execute();
+        let mut step = self.current.take().expect("step record is set while executing");
+        result?;
+
+        step.status = self.status;
+        Ok(step)
+    }
+
+    /// Stops `run` right before the instruction at `address` executes.
+    pub fn add_breakpoint(&mut self, address: usize) {
+        self.breakpoints.insert(address);
+    }
+
+    /// Returns whether there was a breakpoint at `address`.
+    pub fn remove_breakpoint(&mut self, address: usize) -> bool {
+        self.breakpoints.remove(&address)
+    }
+
+    pub fn clear_breakpoints(&mut self) {
+        self.breakpoints.clear();
+    }
+
+    pub fn breakpoints(&self) -> impl Iterator<Item = usize> + '_ {
+        self.breakpoints.iter().copied()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{MemoryWrite, Step};
+    use crate::{assemble, IntcodeMachine, MachineStatus};
+
+    #[test]
+    fn test_step_reports_writes() {
+        let tape: Vec<i64> = vec![1, 9, 10, 3, 2, 3, 11, 0, 99, 30, 40, 50];
+        let mut machine = IntcodeMachine::new(tape);
+
+        let step = machine.step().unwrap();
+        assert_eq!(step.instruction.to_string(), "add [9], [10], [3]");
+        assert_eq!(step.writes, vec![MemoryWrite { address: 3, old: 3, new: 70 }]);
+        assert_eq!(step.status, MachineStatus::Run);
+        assert_eq!(machine.position, 4);
+
+        let step = machine.step().unwrap();
+        assert_eq!(step.writes, vec![MemoryWrite { address: 0, old: 1, new: 3500 }]);
+
+        let step = machine.step().unwrap();
+        assert_eq!(step.status, MachineStatus::Halt);
+        assert!(machine.halted());
+    }
+
+    #[test]
+    fn test_step_reports_input_and_output() {
+        let tape: Vec<i64> = vec![3, 0, 4, 0, 99];
+        let mut machine = IntcodeMachine::new(tape);
+
+        let step = machine.step().unwrap();
+        assert_eq!(step.status, MachineStatus::Yield);
+        assert_eq!(step.input, None);
+        assert_eq!(machine.position, 0);
+
+        machine.add_input(1234);
+        let Step { input, writes, .. } = machine.step().unwrap();
+        assert_eq!(input, Some(1234));
+        assert_eq!(writes, vec![MemoryWrite { address: 0, old: 3, new: 1234 }]);
+
+        assert_eq!(machine.step().unwrap().output, Some(1234));
+    }
+
+    #[test]
+    fn test_breakpoint_stops_run() {
+        let tape = assemble("
+            add #1, #2, [counter]
+            ld [counter]
+            halt
+        counter: .data 0
+        ").unwrap();
+        let mut machine = IntcodeMachine::new(tape);
+        machine.add_breakpoint(4);
+
+        assert_eq!(machine.run(), vec![]);
+        assert_eq!(machine.status(), MachineStatus::Breakpoint);
+        assert_eq!(machine.position, 4);
+
+        assert_eq!(machine.run(), vec![3]);
+        assert!(machine.halted());
+    }
+
+    #[test]
+    fn test_breakpoint_survives_new_input() {
+        let tape: Vec<i64> = vec![3, 0, 4, 0, 99];
+        let mut machine = IntcodeMachine::new(tape);
+        machine.add_breakpoint(0);
+
+        machine.run();
+        assert_eq!(machine.status(), MachineStatus::Breakpoint);
+
+        machine.add_input(7);
+        assert_eq!(machine.run(), vec![7]);
+        assert!(machine.remove_breakpoint(0));
+        assert_eq!(machine.breakpoints().count(), 0);
+    }
+}
@@ -0,0 +1,527 @@
+use std::collections::vec_deque::VecDeque;
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::error::Error;
+use std::fmt;
+use std::ops::{Add, Mul};
+
+use crate::instruction::{Opcode, ParameterMode};
+use crate::{IntcodeError, MachineStatus};
+
+/// A constant plus a sum of named symbols, each multiplied by a non-zero coefficient.
+#[derive(Debug, Clone, Default, Eq, PartialEq, Hash)]
+pub struct Linear {
+    pub constant: i64,
+    pub terms: BTreeMap<String, i64>,
+}
+
+impl Linear {
+    /// The expression multiplied by `factor`, or `None` if a coefficient overflows.
+    pub fn checked_scale(&self, factor: i64) -> Option<Linear> {
+        if factor == 0 {
+            return Some(Linear::default());
+        }
+        let mut terms = BTreeMap::new();
+        for (symbol, &coefficient) in &self.terms {
+            terms.insert(symbol.clone(), coefficient.checked_mul(factor)?);
+        }
+        Some(Linear { constant: self.constant.checked_mul(factor)?, terms })
+    }
+
+    /// The sum of both expressions, or `None` if a coefficient overflows.
+    pub fn checked_add(&self, other: &Linear) -> Option<Linear> {
+        let mut sum = self.clone();
+        sum.constant = sum.constant.checked_add(other.constant)?;
+        for (symbol, &coefficient) in &other.terms {
+            let total = sum.terms.get(symbol).copied().unwrap_or(0).checked_add(coefficient)?;
+            if total == 0 {
+                sum.terms.remove(symbol);
+            } else {
+                sum.terms.insert(symbol.clone(), total);
+            }
+        }
+        Some(sum)
+    }
+
+    /// Replaces the symbols with a value in `values`, keeping the others.
+    /// Symbols with a zero coefficient are dropped. Returns `None` if the constant does not fit in an `i64`.
+    pub fn substitute(&self, values: &BTreeMap<String, i64>) -> Option<Linear> {
+        let mut terms = BTreeMap::new();
+        // Summed in an i128 so that terms cancelling each other out do not overflow on the way.
+        let mut constant = self.constant as i128;
+        for (symbol, &coefficient) in &self.terms {
+            match values.get(symbol) {
+                Some(&value) => constant = constant.checked_add(coefficient as i128 * value as i128)?,
+                None if coefficient == 0 => {}
+                None => {
+                    terms.insert(symbol.clone(), coefficient);
+                }
+            }
+        }
+        Some(Linear { constant: i64::try_from(constant).ok()?, terms })
+    }
+}
+
+/// A value computed by a `SymbolicMachine`. Sums and products that stay linear in the symbols are kept as a `Linear`,
+/// anything else becomes a tree of operations, and so do linear results whose coefficients would overflow.
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub enum Expr {
+    Linear(Linear),
+    Add(Box<Expr>, Box<Expr>),
+    Mul(Box<Expr>, Box<Expr>),
+    /// 1 if the first operand is less than the second, 0 otherwise.
+    LessThan(Box<Expr>, Box<Expr>),
+    /// 1 if both operands are equal, 0 otherwise.
+    Equals(Box<Expr>, Box<Expr>),
+    /// The value of the cell at a symbolic address.
+    Load(Box<Expr>),
+}
+
+impl Expr {
+    pub fn constant(value: i64) -> Expr {
+        Expr::Linear(Linear { constant: value, terms: BTreeMap::new() })
+    }
+
+    pub fn symbol(name: &str) -> Expr {
+        let mut terms = BTreeMap::new();
+        terms.insert(name.to_string(), 1);
+        Expr::Linear(Linear { constant: 0, terms })
+    }
+
+    pub fn as_linear(&self) -> Option<&Linear> {
+        match self {
+            Expr::Linear(linear) => Some(linear),
+            _ => None,
+        }
+    }
+
+    pub fn as_constant(&self) -> Option<i64> {
+        self.as_linear().filter(|linear| linear.terms.is_empty()).map(|linear| linear.constant)
+    }
+
+    pub fn less_than(self, other: Expr) -> Expr {
+        match (self.as_constant(), other.as_constant()) {
+            (Some(a), Some(b)) => Expr::constant((a < b) as i64),
+            _ => Expr::LessThan(Box::new(self), Box::new(other)),
+        }
+    }
+
+    /// Whether the value depends on the contents of memory.
+    pub fn loads(&self) -> bool {
+        match self {
+            Expr::Linear(_) => false,
+            Expr::Add(a, b) | Expr::Mul(a, b) | Expr::LessThan(a, b) | Expr::Equals(a, b) => a.loads() || b.loads(),
+            Expr::Load(_) => true,
+        }
+    }
+
+    pub fn equals(self, other: Expr) -> Expr {
+        // Two loads from the same address can see different values when the cell is written in between.
+        if self == other && !self.loads() {
+            return Expr::constant(1);
+        }
+        match (self.as_constant(), other.as_constant()) {
+            (Some(a), Some(b)) => Expr::constant((a == b) as i64),
+            _ => Expr::Equals(Box::new(self), Box::new(other)),
+        }
+    }
+
+    /// The value of the expression once every symbol is given a value. `None` if a symbol is missing,
+    /// the expression loads from memory or the value overflows.
+    pub fn eval(&self, values: &BTreeMap<String, i64>) -> Option<i64> {
+        match self {
+            Expr::Linear(linear) => {
+                let value = linear.substitute(values)?;
+                Some(value.constant).filter(|_| value.terms.is_empty())
+            }
+            Expr::Add(a, b) => a.eval(values)?.checked_add(b.eval(values)?),
+            Expr::Mul(a, b) => a.eval(values)?.checked_mul(b.eval(values)?),
+            Expr::LessThan(a, b) => Some((a.eval(values)? < b.eval(values)?) as i64),
+            Expr::Equals(a, b) => Some((a.eval(values)? == b.eval(values)?) as i64),
+            Expr::Load(_) => None,
+        }
+    }
+}
+
+impl Add for Expr {
+    type Output = Expr;
+
+    fn add(self, other: Expr) -> Expr {
+        match (&self, &other) {
+            (Expr::Linear(a), Expr::Linear(b)) => match a.checked_add(b) {
+                Some(sum) => Expr::Linear(sum),
+                None => Expr::Add(Box::new(self), Box::new(other)),
+            },
+            _ => Expr::Add(Box::new(self), Box::new(other)),
+        }
+    }
+}
+
+impl Mul for Expr {
+    type Output = Expr;
+
+    fn mul(self, other: Expr) -> Expr {
+        let product = match (&self, &other, self.as_constant(), other.as_constant()) {
+            (_, Expr::Linear(linear), Some(factor), _) | (Expr::Linear(linear), _, _, Some(factor)) => {
+                linear.checked_scale(factor)
+            }
+            _ => None,
+        };
+        match product {
+            Some(product) => Expr::Linear(product),
+            None => Expr::Mul(Box::new(self), Box::new(other)),
+        }
+    }
+}
+
+impl fmt::Display for Linear {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut first = true;
+        for (symbol, &coefficient) in &self.terms {
+            let sign = match (first, coefficient < 0) {
+                (true, true) => "-",
+                (true, false) => "",
+                (false, true) => " - ",
+                (false, false) => " + ",
+            };
+            match coefficient.unsigned_abs() {
+                1 => write!(f, "{}{}", sign, symbol)?,
+                factor => write!(f, "{}{}*{}", sign, factor, symbol)?,
+            }
+            first = false;
+        }
+
+        match (first, self.constant) {
+            (true, constant) => write!(f, "{}", constant),
+            (false, 0) => Ok(()),
+            (false, constant) if constant < 0 => write!(f, " - {}", constant.unsigned_abs()),
+            (false, constant) => write!(f, " + {}", constant),
+        }
+    }
+}
+
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Expr::Linear(linear) => write!(f, "{}", linear),
+            Expr::Add(a, b) => write!(f, "({}) + ({})", a, b),
+            Expr::Mul(a, b) => write!(f, "({}) * ({})", a, b),
+            Expr::LessThan(a, b) => write!(f, "({}) < ({})", a, b),
+            Expr::Equals(a, b) => write!(f, "({}) == ({})", a, b),
+            Expr::Load(address) => write!(f, "[{}]", address),
+        }
+    }
+}
+
+/// Errors raised while executing a program symbolically.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum SymbolicError {
+    /// The program fails the same way it would on an `IntcodeMachine`.
+    Machine(IntcodeError),
+    /// The instruction at `position` needs a concrete value but got an expression: as its opcode, as an address
+    /// to write to, as the condition or target of a jump, or as a relative base adjustment.
+    Symbolic { position: usize, value: Expr },
+}
+
+impl fmt::Display for SymbolicError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SymbolicError::Machine(error) => write!(f, "{}", error),
+            SymbolicError::Symbolic { position, value } => {
+                write!(f, "Instruction at position {} depends on the symbolic value {}", position, value)
+            }
+        }
+    }
+}
+
+impl Error for SymbolicError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            SymbolicError::Machine(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<IntcodeError> for SymbolicError {
+    fn from(error: IntcodeError) -> Self {
+        SymbolicError::Machine(error)
+    }
+}
+
+/// Runs a program over expressions instead of numbers, so that cells and inputs can hold symbols
+/// and the results tell how they depend on them. Control flow must stay concrete: the machine stops
+/// with an error as soon as a jump, a written address or an opcode depends on a symbol.
+/// Reads from symbolic addresses give a `Load`, which is fine as long as the value is overwritten before it matters.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct SymbolicMachine {
+    tape: Vec<Expr>,
+    position: usize,
+    relative_base: i64,
+    input: VecDeque<Expr>,
+    output: Vec<Expr>,
+    status: MachineStatus,
+}
+
+impl SymbolicMachine {
+    pub fn new(tape: Vec<i64>) -> SymbolicMachine {
+        SymbolicMachine {
+            tape: tape.into_iter().map(Expr::constant).collect(),
+            position: 0,
+            relative_base: 0,
+            input: VecDeque::new(),
+            output: vec![],
+            status: MachineStatus::Run,
+        }
+    }
+
+    pub fn with_init(mut self, noun: Expr, verb: Expr) -> Self {
+        self.set(1, noun);
+        self.set(2, verb);
+        self
+    }
+
+    pub fn with_input(mut self, input: Expr) -> Self {
+        self.add_input(input);
+        self
+    }
+
+    pub fn add_input(&mut self, input: Expr) {
+        if self.status != MachineStatus::Halt {
+            if self.status == MachineStatus::Yield {
+                self.status = MachineStatus::Run;
+            }
+            self.input.push_back(input);
+        }
+    }
+
+    pub fn get(&self, address: usize) -> Expr {
+        self.tape.get(address).cloned().unwrap_or_else(|| Expr::constant(0))
+    }
+
+    pub fn set(&mut self, address: usize, value: Expr) {
+        if address >= self.tape.len() {
+            self.tape.resize((address * 2).max(address + 1), Expr::constant(0));
+        }
+        self.tape[address] = value;
+    }
+
+    pub fn status(&self) -> MachineStatus {
+        self.status
+    }
+
+    pub fn halted(&self) -> bool {
+        self.status == MachineStatus::Halt
+    }
+
+    pub fn yielded(&self) -> bool {
+        self.status == MachineStatus::Yield
+    }
+
+    /// Runs until the program halts or waits for input, returning what it output along the way.
+    pub fn run(&mut self) -> Result<Vec<Expr>, SymbolicError> {
+        self.output.clear();
+        while self.status == MachineStatus::Run {
+            self.execute()?;
+        }
+        Ok(self.output.clone())
+    }
+
+    /// Runs the program and returns the expression left in `target`.
+    pub fn run_for_target(&mut self, target: usize) -> Result<Expr, SymbolicError> {
+        self.run()?;
+        Ok(self.get(target))
+    }
+
+    fn concrete(&self, value: Expr) -> Result<i64, SymbolicError> {
+        value.as_constant().ok_or(SymbolicError::Symbolic { position: self.position, value })
+    }
+
+    fn address(&self, address: i64, instruction: i64) -> Result<usize, SymbolicError> {
+        if address < 0 {
+            return Err(IntcodeError::NegativeAddress { position: self.position, instruction, address }.into());
+        }
+        Ok(address as usize)
+    }
+
+    /// The address a positional or relative parameter refers to, as an expression.
+    fn parameter_address(&self, mode: ParameterMode, value: Expr) -> Expr {
+        match mode {
+            ParameterMode::Relative => Expr::constant(self.relative_base) + value,
+            _ => value,
+        }
+    }
+
+    fn read(&self, mode: ParameterMode, value: Expr, instruction: i64) -> Result<Expr, SymbolicError> {
+        if mode == ParameterMode::Immediate {
+            return Ok(value);
+        }
+        let address = self.parameter_address(mode, value);
+        match address.as_constant() {
+            Some(address) => Ok(self.get(self.address(address, instruction)?)),
+            None => Ok(Expr::Load(Box::new(address))),
+        }
+    }
+
+    fn dest(&self, mode: ParameterMode, value: Expr, instruction: i64) -> Result<usize, SymbolicError> {
+        if mode == ParameterMode::Immediate {
+            return Err(IntcodeError::ImmediateWrite { position: self.position, instruction }.into());
+        }
+        let address = self.concrete(self.parameter_address(mode, value))?;
+        self.address(address, instruction)
+    }
+
+    fn execute(&mut self) -> Result<(), SymbolicError> {
+        let position = self.position;
+        let instruction = self.concrete(self.get(position))?;
+        let opcode = Opcode::from_code(instruction % 100)
+            .ok_or(IntcodeError::UnknownOpcode { position, instruction })?;
+
+        let mut modes = instruction / 100;
+        let mut parameters = Vec::with_capacity(opcode.arity());
+        for offset in 1..=opcode.arity() {
+            let mode = ParameterMode::from_digit(modes % 10)
+                .ok_or(IntcodeError::InvalidParameterMode { position, instruction, mode: modes % 10 })?;
+            parameters.push((mode, self.get(position + offset)));
+            modes /= 10;
+        }
+        let read = |index: usize| {
+            let (mode, value) = parameters[index].clone();
+            self.read(mode, value, instruction)
+        };
+
+        match opcode {
+            Opcode::Add | Opcode::Mul | Opcode::Tlt | Opcode::Teq => {
+                let (a, b) = (read(0)?, read(1)?);
+                let (mode, value) = parameters[2].clone();
+                let dest = self.dest(mode, value, instruction)?;
+                let result = match opcode {
+                    Opcode::Add => a + b,
+                    Opcode::Mul => a * b,
+                    Opcode::Tlt => a.less_than(b),
+                    _ => a.equals(b),
+                };
+                self.set(dest, result);
+                self.position += 4;
+            }
+            Opcode::St => {
+                let (mode, value) = parameters[0].clone();
+                let dest = self.dest(mode, value, instruction)?;
+                match self.input.pop_front() {
+                    Some(input) => {
+                        self.set(dest, input);
+                        self.position += 2;
+                    }
+                    None => self.status = MachineStatus::Yield,
+                }
+            }
+            Opcode::Ld => {
+                let value = read(0)?;
+                self.output.push(value);
+                self.position += 2;
+            }
+            Opcode::Jnz | Opcode::Jz => {
+                let condition = self.concrete(read(0)?)?;
+                let target = self.concrete(read(1)?)?;
+                if (condition != 0) == (opcode == Opcode::Jnz) {
+                    if target < 0 || target as usize >= self.tape.len() {
+                        return Err(IntcodeError::JumpOutOfRange { position, instruction, target }.into());
+                    }
+                    self.position = target as usize;
+                } else {
+                    self.position += 3;
+                }
+            }
+            Opcode::Rel => {
+                self.relative_base = self.relative_base.checked_add(self.concrete(read(0)?)?)
+                    .ok_or(IntcodeError::Overflow { position, instruction })?;
+                self.position += 2;
+            }
+            Opcode::Halt => self.status = MachineStatus::Halt,
+            Opcode::Custom { .. } => unreachable!("only built-in opcodes are decoded"),
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use super::{Expr, SymbolicError, SymbolicMachine};
+    use crate::fixtures::echo_sum_program;
+    use crate::{IntcodeError, IntcodeMachine};
+
+    /// Shaped like the gravity assist program: address 0 ends up as `1000 * noun + verb + 7`.
+    fn affine_program() -> Vec<i64> {
+        vec![1, 0, 0, 3, 2, 1, 17, 18, 1, 18, 2, 0, 1, 0, 19, 0, 99, 1000, 0, 7]
+    }
+
+    #[test]
+    fn test_affine_result() {
+        let mut machine = SymbolicMachine::new(affine_program())
+            .with_init(Expr::symbol("noun"), Expr::symbol("verb"));
+        let result = machine.run_for_target(0).unwrap();
+
+        assert_eq!(result.to_string(), "1000*noun + verb + 7");
+        let values: BTreeMap<String, i64> = vec![("noun".to_string(), 12), ("verb".to_string(), 2)].into_iter().collect();
+        assert_eq!(result.eval(&values), Some(IntcodeMachine::new(affine_program()).with_init(12, 2).run_for_target(0)));
+    }
+
+    #[test]
+    fn test_symbolic_inputs() {
+        let mut machine = SymbolicMachine::new(echo_sum_program()).with_input(Expr::symbol("a"));
+
+        assert_eq!(machine.run(), Ok(vec![]));
+        assert!(machine.yielded());
+        machine.add_input(Expr::symbol("a") * Expr::constant(2) + Expr::constant(5));
+        assert_eq!(machine.run().unwrap()[0].to_string(), "3*a + 5");
+        assert!(machine.halted());
+    }
+
+    #[test]
+    fn test_symbolic_jump_is_an_error() {
+        let tape = vec![3, 9, 1005, 9, 7, 104, 0, 104, 1, 0];
+        let mut machine = SymbolicMachine::new(tape).with_input(Expr::symbol("x"));
+
+        assert_eq!(machine.run(), Err(SymbolicError::Symbolic { position: 2, value: Expr::symbol("x") }));
+    }
+
+    #[test]
+    fn test_nonlinear_expressions() {
+        let product = Expr::symbol("x") * Expr::symbol("y") + Expr::constant(1);
+
+        assert_eq!(product.as_linear(), None);
+        assert_eq!(product.to_string(), "((x) * (y)) + (1)");
+        assert_eq!((Expr::symbol("x") * Expr::constant(-2)).to_string(), "-2*x");
+        assert_eq!(Expr::symbol("x").less_than(Expr::constant(3)).to_string(), "(x) < (3)");
+        assert_eq!(Expr::constant(2).less_than(Expr::constant(3)), Expr::constant(1));
+    }
+
+    #[test]
+    fn test_loads_are_never_folded_as_equal() {
+        let load = Expr::Load(Box::new(Expr::symbol("x")));
+
+        assert_eq!(load.clone().equals(load.clone()).to_string(), "([x]) == ([x])");
+        assert_eq!(Expr::symbol("x").equals(Expr::symbol("x")), Expr::constant(1));
+    }
+
+    #[test]
+    fn test_overflowing_coefficients_stay_a_tree() {
+        let big = Expr::symbol("x") * Expr::constant(i64::MAX);
+
+        assert_eq!((big.clone() + big.clone()).as_linear(), None);
+        assert_eq!((big.clone() * Expr::constant(2)).as_linear(), None);
+        let values: BTreeMap<String, i64> = vec![("x".to_string(), 2)].into_iter().collect();
+        assert_eq!(big.eval(&values), None);
+    }
+
+    #[test]
+    fn test_extreme_values() {
+        assert_eq!((Expr::symbol("x") + Expr::constant(i64::MIN)).to_string(), "x - 9223372036854775808");
+        assert_eq!((Expr::symbol("x") * Expr::constant(i64::MIN)).to_string(), "-9223372036854775808*x");
+
+        let mut machine = SymbolicMachine::new(vec![109, i64::MAX, 109, 1, 99]);
+        assert_eq!(machine.run(), Err(SymbolicError::Machine(IntcodeError::Overflow { position: 2, instruction: 109 })));
+    }
+}
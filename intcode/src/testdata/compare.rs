@@ -0,0 +1,143 @@
+// Generated by intcode-compile from a 47 cell tape. Do not edit.
+use intcode::CompiledMachine;
+
+pub const TAPE: &[i64] = &[3, 21, 1008, 21, 8, 20, 1005, 20, 22, 107, 8, 21, 20, 1006, 20, 31, 1106, 0, 36, 98, 0, 0, 1002, 21, 125, 20, 4, 20, 1105, 1, 46, 104, 999, 1105, 1, 46, 1101, 1000, 1, 20, 4, 20, 1105, 1, 46, 98, 99];
+
+/// Cells holding compiled instructions.
+pub const CODE: &[usize] = &[0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17, 18, 22, 23, 24, 25, 26, 27, 28, 29, 30, 31, 32, 33, 34, 35, 36, 37, 38, 39, 40, 41, 42, 43, 44, 46];
+
+pub fn machine() -> CompiledMachine {
+    CompiledMachine::new(TAPE, CODE, run)
+}
+
+pub fn run(m: &mut CompiledMachine) -> Option<()> {
+    loop {
+        match m.pc {
+            0 => {
+                // st [21]
+                let dest = 21;
+                let input = match m.input() {
+                    Some(input) => input,
+                    None => return m.wait(),
+                };
+                m.store(dest, input);
+                m.pc = 2;
+            }
+            2 => {
+                // teq [21], #8, [20]
+                let a: i64 = m.read(21)?;
+                let b: i64 = 8;
+                let dest = 20;
+                m.store(dest, (a == b) as i64);
+                m.pc = 6;
+            }
+            6 => {
+                // jnz [20], #22
+                let a: i64 = m.read(20)?;
+                let b: i64 = 22;
+                if a != 0 {
+                    m.jump(b)?;
+                } else {
+                    m.pc = 9;
+                }
+            }
+            9 => {
+                // tlt #8, [21], [20]
+                let a: i64 = 8;
+                let b: i64 = m.read(21)?;
+                let dest = 20;
+                m.store(dest, (a < b) as i64);
+                m.pc = 13;
+            }
+            13 => {
+                // jz [20], #31
+                let a: i64 = m.read(20)?;
+                let b: i64 = 31;
+                if a == 0 {
+                    m.jump(b)?;
+                } else {
+                    m.pc = 16;
+                }
+            }
+            16 => {
+                // jz #0, #36
+                let a: i64 = 0;
+                let b: i64 = 36;
+                if a == 0 {
+                    m.jump(b)?;
+                } else {
+                    m.pc = 19;
+                }
+            }
+            22 => {
+                // mul [21], #125, [20]
+                let a: i64 = m.read(21)?;
+                let b: i64 = 125;
+                let dest = 20;
+                m.store(dest, a.checked_mul(b)?);
+                m.pc = 26;
+            }
+            26 => {
+                // ld [20]
+                let a: i64 = m.read(20)?;
+                m.emit(a);
+                m.pc = 28;
+            }
+            28 => {
+                // jnz #1, #46
+                let a: i64 = 1;
+                let b: i64 = 46;
+                if a != 0 {
+                    m.jump(b)?;
+                } else {
+                    m.pc = 31;
+                }
+            }
+            31 => {
+                // ld #999
+                let a: i64 = 999;
+                m.emit(a);
+                m.pc = 33;
+            }
+            33 => {
+                // jnz #1, #46
+                let a: i64 = 1;
+                let b: i64 = 46;
+                if a != 0 {
+                    m.jump(b)?;
+                } else {
+                    m.pc = 36;
+                }
+            }
+            36 => {
+                // add #1000, #1, [20]
+                let a: i64 = 1000;
+                let b: i64 = 1;
+                let dest = 20;
+                m.store(dest, a.checked_add(b)?);
+                m.pc = 40;
+            }
+            40 => {
+                // ld [20]
+                let a: i64 = m.read(20)?;
+                m.emit(a);
+                m.pc = 42;
+            }
+            42 => {
+                // jnz #1, #46
+                let a: i64 = 1;
+                let b: i64 = 46;
+                if a != 0 {
+                    m.jump(b)?;
+                } else {
+                    m.pc = 45;
+                }
+            }
+            46 => {
+                // halt
+                return m.halt();
+            }
+            _ => return None,
+        }
+    }
+}
@@ -0,0 +1,77 @@
+// Generated by intcode-compile from a 24 cell tape. Do not edit.
+use intcode::CompiledMachine;
+
+pub const TAPE: &[i64] = &[1108, 5000000000, 5000000000, 22, 1005, 22, 9, 104, 0, 104, 1125899906842624, 1102, 34915192, 34915192, 23, 4, 23, 1105, 5000000000, 21, 99, 99, 0, 0];
+
+/// Cells holding compiled instructions.
+pub const CODE: &[usize] = &[0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17, 18, 19, 21];
+
+pub fn machine() -> CompiledMachine {
+    CompiledMachine::new(TAPE, CODE, run)
+}
+
+pub fn run(m: &mut CompiledMachine) -> Option<()> {
+    loop {
+        match m.pc {
+            0 => {
+                // teq #5000000000, #5000000000, [22]
+                let a: i64 = 5000000000;
+                let b: i64 = 5000000000;
+                let dest = 22;
+                m.store(dest, (a == b) as i64);
+                m.pc = 4;
+            }
+            4 => {
+                // jnz [22], #9
+                let a: i64 = m.read(22)?;
+                let b: i64 = 9;
+                if a != 0 {
+                    m.jump(b)?;
+                } else {
+                    m.pc = 7;
+                }
+            }
+            7 => {
+                // ld #0
+                let a: i64 = 0;
+                m.emit(a);
+                m.pc = 9;
+            }
+            9 => {
+                // ld #1125899906842624
+                let a: i64 = 1125899906842624;
+                m.emit(a);
+                m.pc = 11;
+            }
+            11 => {
+                // mul #34915192, #34915192, [23]
+                let a: i64 = 34915192;
+                let b: i64 = 34915192;
+                let dest = 23;
+                m.store(dest, a.checked_mul(b)?);
+                m.pc = 15;
+            }
+            15 => {
+                // ld [23]
+                let a: i64 = m.read(23)?;
+                m.emit(a);
+                m.pc = 17;
+            }
+            17 => {
+                // jnz #5000000000, #21
+                let a: i64 = 5000000000;
+                let b: i64 = 21;
+                if a != 0 {
+                    m.jump(b)?;
+                } else {
+                    m.pc = 20;
+                }
+            }
+            21 => {
+                // halt
+                return m.halt();
+            }
+            _ => return None,
+        }
+    }
+}
@@ -0,0 +1,61 @@
+// Generated by intcode-compile from a 16 cell tape. Do not edit.
+use intcode::CompiledMachine;
+
+pub const TAPE: &[i64] = &[109, 1, 204, -1, 1001, 100, 1, 100, 1008, 100, 16, 101, 1006, 101, 0, 99];
+
+/// Cells holding compiled instructions.
+pub const CODE: &[usize] = &[0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15];
+
+pub fn machine() -> CompiledMachine {
+    CompiledMachine::new(TAPE, CODE, run)
+}
+
+pub fn run(m: &mut CompiledMachine) -> Option<()> {
+    loop {
+        match m.pc {
+            0 => {
+                // rel #1
+                let a: i64 = 1;
+                m.rb = m.rb.checked_add(a)?;
+                m.pc = 2;
+            }
+            2 => {
+                // ld rb-1
+                let a: i64 = m.read_relative(-1)?;
+                m.emit(a);
+                m.pc = 4;
+            }
+            4 => {
+                // add [100], #1, [100]
+                let a: i64 = m.read(100)?;
+                let b: i64 = 1;
+                let dest = 100;
+                m.store(dest, a.checked_add(b)?);
+                m.pc = 8;
+            }
+            8 => {
+                // teq [100], #16, [101]
+                let a: i64 = m.read(100)?;
+                let b: i64 = 16;
+                let dest = 101;
+                m.store(dest, (a == b) as i64);
+                m.pc = 12;
+            }
+            12 => {
+                // jz [101], #0
+                let a: i64 = m.read(101)?;
+                let b: i64 = 0;
+                if a == 0 {
+                    m.jump(b)?;
+                } else {
+                    m.pc = 15;
+                }
+            }
+            15 => {
+                // halt
+                return m.halt();
+            }
+            _ => return None,
+        }
+    }
+}
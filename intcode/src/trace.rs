@@ -0,0 +1,457 @@
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::mem;
+use std::sync::{Arc, Mutex};
+
+use crate::instruction::{Instruction, Opcode, Operand, ParameterMode};
+use crate::isa::InstructionSet;
+use crate::save::{status_code, status_from_code};
+use crate::{Input, IntcodeMachine, MachineStatus, Memory, MemoryRead, MemoryWrite, Output, Step};
+
+/// Every binary trace starts with these bytes, followed by a format version byte.
+const MAGIC: &[u8; 4] = b"ICT\0";
+/// Version 2 added the `OutOfBudget` and `Loop` statuses, version 1 traces still read.
+const VERSION: u8 = 2;
+
+const RELATIVE_BASE_FLAG: u8 = 1;
+const INPUT_FLAG: u8 = 2;
+const OUTPUT_FLAG: u8 = 4;
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum TraceFormat {
+    /// One line per instruction, as printed by `Step`'s `Display`. Meant to be read and diffed.
+    Text,
+    /// Variable length integers, a few bytes per instruction. Read it back with `read_binary_trace`,
+    /// or `read_binary_trace_in` for a machine with an instruction set.
+    Binary,
+}
+
+/// Writes executed instructions to any `io::Write`.
+/// As a `TraceSink` it keeps the first write error, see `error`, and drops the steps that follow.
+pub struct TraceWriter<W: Write> {
+    writer: W,
+    format: TraceFormat,
+    started: bool,
+    error: Option<io::Error>,
+}
+
+/// Receives every instruction a traced machine executes, as it executes.
+pub trait TraceSink: Send {
+    fn record(&mut self, step: &Step);
+}
+
+impl TraceSink for Vec<Step> {
+    fn record(&mut self, step: &Step) {
+        self.push(step.clone());
+    }
+}
+
+impl<W: Write + Send> TraceSink for TraceWriter<W> {
+    fn record(&mut self, step: &Step) {
+        if self.error.is_none() {
+            self.error = self.write(step).err();
+        }
+    }
+}
+
+/// Lets the caller keep a handle on a sink the machine writes to, to flush it or check its errors.
+impl<S: TraceSink> TraceSink for Arc<Mutex<S>> {
+    fn record(&mut self, step: &Step) {
+        self.lock().unwrap().record(step);
+    }
+}
+
+impl<F: FnMut(&Step) + Send> TraceSink for F {
+    fn record(&mut self, step: &Step) {
+        self(step)
+    }
+}
+
+/// Where the steps of a traced machine go. Clones of a streaming machine share its sink.
+#[derive(Clone)]
+pub(crate) enum Tracer {
+    /// Keeps the steps in the machine until `take_trace`.
+    Collect,
+    Stream(Arc<Mutex<dyn TraceSink>>),
+}
+
+impl<I: Input, O: Output, M: Memory> IntcodeMachine<I, O, M> {
+    /// Records every instruction executed from now on, by `run` as well as `step`.
+    /// The records pile up in memory until they are collected with `take_trace`;
+    /// long runs should stream them with `start_tracing_to` instead.
+    pub fn start_tracing(&mut self) {
+        self.tracer = Some(Tracer::Collect);
+    }
+
+    /// Sends every instruction executed from now on to `sink`, without keeping them.
+    pub fn start_tracing_to<S: TraceSink + 'static>(&mut self, sink: S) {
+        self.tracer = Some(Tracer::Stream(Arc::new(Mutex::new(sink))));
+    }
+
+    /// Stops recording instructions, dropping any sink tracing streamed to.
+    pub fn stop_tracing(&mut self) {
+        self.tracer = None;
+    }
+
+    /// Returns the instructions recorded since tracing started or the trace was last taken.
+    pub fn take_trace(&mut self) -> Vec<Step> {
+        mem::take(&mut self.trace)
+    }
+
+    /// Passes the record of an executed instruction on to the tracer, if tracing.
+    pub(crate) fn record_trace(&mut self, step: &Step) {
+        match &self.tracer {
+            Some(Tracer::Collect) => self.trace.record(step),
+            Some(Tracer::Stream(sink)) => sink.lock().unwrap().record(step),
+            None => {}
+        }
+    }
+}
+
+/// Index of the first instruction where two traces take a different path,
+/// or where the shorter trace ends. `None` if both executed the same instructions.
+pub fn first_divergence(a: &[Step], b: &[Step]) -> Option<usize> {
+    let common = a.iter().zip(b)
+        .position(|(a, b)| a.instruction.address != b.instruction.address);
+
+    match common {
+        Some(index) => Some(index),
+        None if a.len() != b.len() => Some(a.len().min(b.len())),
+        None => None,
+    }
+}
+
+impl fmt::Display for Step {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.instruction.address, self.instruction)?;
+
+        if !self.reads.is_empty() {
+            write!(f, " | read")?;
+            for read in &self.reads {
+                write!(f, " [{}]={}", read.address, read.value)?;
+            }
+        }
+        if !self.writes.is_empty() {
+            write!(f, " | write")?;
+            for write in &self.writes {
+                write!(f, " [{}] {}->{}", write.address, write.old, write.new)?;
+            }
+        }
+        if let Some((old, new)) = self.relative_base {
+            write!(f, " | rb {}->{}", old, new)?;
+        }
+        if let Some(input) = self.input {
+            write!(f, " | in {}", input)?;
+        }
+        if let Some(output) = self.output {
+            write!(f, " | out {}", output)?;
+        }
+        if self.status != MachineStatus::Run {
+            write!(f, " | {:?}", self.status)?;
+        }
+        Ok(())
+    }
+}
+
+fn put_unsigned(bytes: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        bytes.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    bytes.push(value as u8);
+}
+
+/// Zigzag encodes `value` so small negative numbers stay short.
+fn put_signed(bytes: &mut Vec<u8>, value: i64) {
+    put_unsigned(bytes, ((value << 1) ^ (value >> 63)) as u64);
+}
+
+impl<W: Write> TraceWriter<W> {
+    pub fn new(writer: W, format: TraceFormat) -> TraceWriter<W> {
+        TraceWriter { writer, format, started: false, error: None }
+    }
+
+    pub fn write(&mut self, step: &Step) -> io::Result<()> {
+        match self.format {
+            TraceFormat::Text => writeln!(self.writer, "{}", step),
+            TraceFormat::Binary => {
+                let mut bytes = vec![];
+                if !self.started {
+                    bytes.extend_from_slice(MAGIC);
+                    bytes.push(VERSION);
+                    self.started = true;
+                }
+                encode_step(&mut bytes, step);
+                self.writer.write_all(&bytes)
+            }
+        }
+    }
+
+    pub fn write_all(&mut self, steps: &[Step]) -> io::Result<()> {
+        steps.iter().try_for_each(|step| self.write(step))
+    }
+
+    /// The first error writing a step recorded as a `TraceSink`.
+    pub fn error(&self) -> Option<&io::Error> {
+        self.error.as_ref()
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+fn encode_step(bytes: &mut Vec<u8>, step: &Step) {
+    put_unsigned(bytes, step.instruction.address as u64);
+    put_signed(bytes, step.instruction.encode());
+    step.instruction.operands.iter().for_each(|operand| put_signed(bytes, operand.value));
+
+    put_unsigned(bytes, step.reads.len() as u64);
+    for read in &step.reads {
+        put_unsigned(bytes, read.address as u64);
+        put_signed(bytes, read.value);
+    }
+
+    put_unsigned(bytes, step.writes.len() as u64);
+    for write in &step.writes {
+        put_unsigned(bytes, write.address as u64);
+        put_signed(bytes, write.old);
+        put_signed(bytes, write.new);
+    }
+
+    let flags = step.relative_base.map_or(0, |_| RELATIVE_BASE_FLAG)
+        | step.input.map_or(0, |_| INPUT_FLAG)
+        | step.output.map_or(0, |_| OUTPUT_FLAG);
+    bytes.push(flags);
+    if let Some((old, new)) = step.relative_base {
+        put_signed(bytes, old as i64);
+        put_signed(bytes, new as i64);
+    }
+    step.input.iter().chain(step.output.iter()).for_each(|&value| put_signed(bytes, value));
+
+    bytes.push(status_code(step.status));
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("Invalid binary trace: {}", message))
+}
+
+/// Reads fields of a binary trace from the front of a byte slice.
+struct Decoder<'a> {
+    bytes: &'a [u8],
+    /// The instruction set the traced machine ran, to decode its custom opcodes.
+    set: Option<&'a dyn InstructionSet>,
+}
+
+impl<'a> Decoder<'a> {
+    fn byte(&mut self) -> io::Result<u8> {
+        let (&byte, rest) = self.bytes.split_first().ok_or_else(|| invalid("truncated record"))?;
+        self.bytes = rest;
+        Ok(byte)
+    }
+
+    fn unsigned(&mut self) -> io::Result<u64> {
+        let mut value = 0u64;
+        for shift in (0..64).step_by(7) {
+            let byte = self.byte()?;
+            value |= ((byte & 0x7f) as u64) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(invalid("integer too long"))
+    }
+
+    fn signed(&mut self) -> io::Result<i64> {
+        let value = self.unsigned()?;
+        Ok((value >> 1) as i64 ^ -((value & 1) as i64))
+    }
+
+    fn address(&mut self) -> io::Result<usize> {
+        Ok(self.unsigned()? as usize)
+    }
+
+    fn step(&mut self) -> io::Result<Step> {
+        let address = self.address()?;
+        let raw = self.signed()?;
+        let code = raw % 100;
+        let opcode = match self.set {
+            Some(set) => set.definition(code).map(|definition| definition.opcode(code)),
+            None => Opcode::from_code(code),
+        };
+        let opcode = opcode.ok_or_else(|| invalid("unknown opcode"))?;
+
+        let mut modes = raw / 100;
+        let mut operands = vec![];
+        for _ in 0..opcode.arity() {
+            let mode = ParameterMode::from_digit(modes % 10).ok_or_else(|| invalid("unknown parameter mode"))?;
+            operands.push(Operand { mode, value: self.signed()? });
+            modes /= 10;
+        }
+
+        let mut reads = vec![];
+        for _ in 0..self.unsigned()? {
+            reads.push(MemoryRead { address: self.address()?, value: self.signed()? });
+        }
+
+        let mut writes = vec![];
+        for _ in 0..self.unsigned()? {
+            writes.push(MemoryWrite { address: self.address()?, old: self.signed()?, new: self.signed()? });
+        }
+
+        let flags = self.byte()?;
+        let relative_base = match flags & RELATIVE_BASE_FLAG {
+            0 => None,
+            _ => Some((self.signed()? as isize, self.signed()? as isize)),
+        };
+        let input = if flags & INPUT_FLAG != 0 { Some(self.signed()?) } else { None };
+        let output = if flags & OUTPUT_FLAG != 0 { Some(self.signed()?) } else { None };
+        let status = status_from_code(self.byte()?).map_err(|_| invalid("unknown machine status"))?;
+
+        Ok(Step {
+            instruction: Instruction { address, opcode, operands },
+            reads,
+            writes,
+            relative_base,
+            input,
+            output,
+            status,
+        })
+    }
+}
+
+/// Reads back a trace written by a `TraceWriter` in `TraceFormat::Binary`.
+pub fn read_binary_trace<R: Read>(reader: &mut R) -> io::Result<Vec<Step>> {
+    read_trace(reader, None)
+}
+
+/// Reads back a binary trace of a machine running `set`, which may have executed custom instructions.
+pub fn read_binary_trace_in<R: Read>(reader: &mut R, set: &dyn InstructionSet) -> io::Result<Vec<Step>> {
+    read_trace(reader, Some(set))
+}
+
+fn read_trace<R: Read>(reader: &mut R, set: Option<&dyn InstructionSet>) -> io::Result<Vec<Step>> {
+    let mut bytes = vec![];
+    reader.read_to_end(&mut bytes)?;
+    if bytes.is_empty() {
+        return Ok(vec![]);
+    }
+
+    if !bytes.starts_with(MAGIC) {
+        return Err(invalid("missing header"));
+    }
+    if !bytes.get(MAGIC.len()).is_some_and(|&version| (1..=VERSION).contains(&version)) {
+        return Err(invalid("unsupported version"));
+    }
+
+    let mut decoder = Decoder { bytes: &bytes[MAGIC.len() + 1..], set };
+    let mut steps = vec![];
+    while !decoder.bytes.is_empty() {
+        steps.push(decoder.step()?);
+    }
+    Ok(steps)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::{first_divergence, read_binary_trace, TraceFormat, TraceWriter};
+    use crate::fixtures::compare_program;
+    use crate::IntcodeMachine;
+
+    #[test]
+    fn test_trace_records_run() {
+        let mut machine = IntcodeMachine::new(vec![109, 5, 21101, 2, 3, 0, 204, 0, 99]);
+        machine.start_tracing();
+        machine.run();
+
+        let trace = machine.take_trace();
+        let lines: Vec<String> = trace.iter().map(|step| step.to_string()).collect();
+        assert_eq!(lines, vec![
+            "0: rel #5 | read [1]=5 | rb 0->5",
+            "2: add #2, #3, rb+0 | read [3]=2 [4]=3 | write [5] 0->5",
+            "6: ld rb+0 | read [5]=5 | out 5",
+            "8: halt | Halt",
+        ]);
+        assert!(machine.take_trace().is_empty());
+    }
+
+    #[test]
+    fn test_text_trace() {
+        let mut machine = IntcodeMachine::new(vec![3, 0, 4, 0, 99]).with_input(7);
+        machine.start_tracing();
+        machine.run();
+
+        let mut writer = TraceWriter::new(vec![], TraceFormat::Text);
+        writer.write_all(&machine.take_trace()).unwrap();
+
+        assert_eq!(String::from_utf8(writer.into_inner()).unwrap(), "\
+0: st [0] | write [0] 3->7 | in 7
+2: ld [0] | read [0]=7 | out 7
+4: halt | Halt
+");
+    }
+
+    #[test]
+    fn test_binary_trace_round_trip() {
+        let mut machine = IntcodeMachine::new(compare_program()).with_input(8);
+        machine.start_tracing();
+        machine.run();
+        let trace = machine.take_trace();
+
+        let mut writer = TraceWriter::new(vec![], TraceFormat::Binary);
+        writer.write_all(&trace).unwrap();
+        let bytes = writer.into_inner();
+
+        assert_eq!(read_binary_trace(&mut bytes.as_slice()).unwrap(), trace);
+        assert!(read_binary_trace(&mut &bytes[..bytes.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn test_streamed_trace() {
+        let writer = Arc::new(Mutex::new(TraceWriter::new(vec![], TraceFormat::Text)));
+        let mut machine = IntcodeMachine::new(vec![3, 0, 4, 0, 99]).with_input(7);
+        machine.start_tracing_to(writer.clone());
+        machine.run();
+        machine.stop_tracing();
+
+        let writer = Arc::try_unwrap(writer).ok().unwrap().into_inner().unwrap();
+        assert!(writer.error().is_none());
+        assert_eq!(String::from_utf8(writer.into_inner()).unwrap(), "\
+0: st [0] | write [0] 3->7 | in 7
+2: ld [0] | read [0]=7 | out 7
+4: halt | Halt
+");
+        assert!(machine.take_trace().is_empty());
+    }
+
+    #[test]
+    fn test_trace_to_closure() {
+        let mut count = 0;
+        let counter = Arc::new(Mutex::new(0));
+        let shared = counter.clone();
+        let mut machine = IntcodeMachine::new(compare_program()).with_input(8);
+        machine.start_tracing_to(move |_: &_| *shared.lock().unwrap() += 1);
+        while !machine.halted() {
+            machine.step().unwrap();
+            count += 1;
+        }
+
+        assert_eq!(*counter.lock().unwrap(), count);
+    }
+
+    #[test]
+    fn test_first_divergence() {
+        let trace = |input| {
+            let mut machine = IntcodeMachine::new(compare_program()).with_input(input);
+            machine.start_tracing();
+            machine.run();
+            machine.take_trace()
+        };
+
+        assert_eq!(first_divergence(&trace(7), &trace(8)), Some(3));
+        assert_eq!(first_divergence(&trace(7), &trace(6)), None);
+        assert_eq!(first_divergence(&trace(9), &trace(9)[..2]), Some(2));
+    }
+}
@@ -0,0 +1,103 @@
+use crate::{Input, IntcodeMachine, Memory, Output};
+
+/// How an instruction touched a watched cell.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Access {
+    Read,
+    Write,
+}
+
+/// An access to a watched cell. Reads have the same old and new value.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct WatchHit {
+    pub address: usize,
+    pub access: Access,
+    pub old: i64,
+    pub new: i64,
+    /// Position of the instruction that accessed the cell.
+    pub position: usize,
+}
+
+impl<I: Input, O: Output, M: Memory> IntcodeMachine<I, O, M> {
+    /// Pauses `run` with a `Watchpoint` status after any instruction that reads or writes the cell at `address`.
+    pub fn add_watchpoint(&mut self, address: usize) {
+        self.watchpoints.insert(address);
+    }
+
+    /// Returns whether there was a watchpoint at `address`.
+    pub fn remove_watchpoint(&mut self, address: usize) -> bool {
+        self.watchpoints.remove(&address)
+    }
+
+    pub fn clear_watchpoints(&mut self) {
+        self.watchpoints.clear();
+    }
+
+    pub fn watchpoints(&self) -> impl Iterator<Item = usize> + '_ {
+        self.watchpoints.iter().copied()
+    }
+
+    /// Accesses to watched cells made by the last instruction executed.
+    pub fn watch_hits(&self) -> &[WatchHit] {
+        &self.watch_hits
+    }
+
+    /// Records an access to `address` if it is being watched.
+    pub(crate) fn watch(&mut self, address: usize, access: Access, old: i64, new: i64) {
+        if !self.watchpoints.is_empty() && self.watchpoints.contains(&address) {
+            self.watch_hits.push(WatchHit { address, access, old, new, position: self.position });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Access, WatchHit};
+    use crate::fixtures::counter_program;
+    use crate::{IntcodeMachine, MachineStatus};
+
+    #[test]
+    fn test_watchpoint_pauses_on_read_and_write() {
+        let mut machine = IntcodeMachine::new(counter_program(3));
+        machine.add_watchpoint(14);
+
+        machine.run();
+        assert_eq!(machine.status(), MachineStatus::Watchpoint);
+        assert_eq!(machine.watch_hits(), &[
+            WatchHit { address: 14, access: Access::Read, old: 0, new: 0, position: 0 },
+            WatchHit { address: 14, access: Access::Write, old: 0, new: 1, position: 0 },
+        ]);
+
+        machine.run();
+        assert_eq!(machine.watch_hits(), &[
+            WatchHit { address: 14, access: Access::Read, old: 1, new: 1, position: 4 },
+        ]);
+    }
+
+    #[test]
+    fn test_watchpoint_resumes_until_halt() {
+        let mut machine = IntcodeMachine::new(counter_program(3));
+        machine.add_watchpoint(15);
+
+        let mut writes = vec![];
+        while !machine.halted() {
+            machine.run();
+            writes.extend(machine.watch_hits().iter()
+                .filter(|hit| hit.access == Access::Write)
+                .map(|hit| hit.new));
+        }
+
+        assert_eq!(writes, vec![0, 0, 1]);
+        assert_eq!(machine.output(), &[3]);
+    }
+
+    #[test]
+    fn test_unwatched_cells_do_not_pause() {
+        let mut machine = IntcodeMachine::new(counter_program(3));
+        machine.add_watchpoint(14);
+        assert!(machine.remove_watchpoint(14));
+
+        assert_eq!(machine.run(), vec![3]);
+        assert!(machine.watch_hits().is_empty());
+    }
+}